@@ -1,27 +1,50 @@
 use crate::deployer;
-use crate::printer::{color_println, color_println_fmt, Color};
+use crate::logs::poll::{catch_interrupts, interrupted};
+use crate::logs::{
+    collect_sorted, sample_rates, FollowEnd, LogFollower, LogLine, LogPrinter, LogsArgs, Output,
+    WaitError,
+};
+use crate::output::{emit, OutputFormat, PartialFailure};
+use crate::printer::{
+    color_println, color_println_fmt, terminal_width, AlternateScreen, Color, Table,
+};
+use crate::runtime::{ContainerRuntime, LogOptions, RuntimeKind};
+use crate::selector::{target_names, Selector, Target};
+use crate::sink::{Archive, Loki, LokiEndpoint, Syslog, SyslogTarget};
+use crate::stats::{collect_stats, render_table, sort_rows, ContainerStats, StatsArgs};
 use crate::utils::{
-    get_containers_from_stack, is_terminal, kill_containers, list_containers, parse_inspect_data,
-    parse_stats_data, spawn_container_logger, update_container_by_name, InspectData, StatsData,
+    format_size, is_terminal, kill_containers, list_containers, log_file_size, restart_container,
+    update_container_by_name, RestartResult, UpdateProgress, UpdateResult,
 };
-use anyhow::Context;
+use anyhow::{bail, Context};
+use chrono::{Local, Utc};
 use std::collections::hash_map::HashMap;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::{self, Write};
-use std::process::Command;
+use std::io::{self, BufRead, Write};
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
 
-pub const DOCKER: &str = "docker";
 const DSD: &str = "docker-stack-deploy";
 
+/// How often Ctrl-C is checked for between stats refreshes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Initializes a new instance of docker-stack-deploy
-pub fn init(project_dir: Option<String>, git_url: String) -> anyhow::Result<()> {
-    let project_dir = project_dir.unwrap_or_else(deployer::default_project_dir);
-    let host_sock = deployer::resolve_host_sock()?;
+pub fn init(
+    runtime: &dyn ContainerRuntime,
+    kind: RuntimeKind,
+    project_dir: Option<String>,
+    git_url: String,
+) -> anyhow::Result<()> {
+    let kind = kind.detect();
+    let project_dir = project_dir.unwrap_or_else(|| deployer::default_project_dir(kind));
+    let host_sock = deployer::resolve_host_sock(kind)?;
 
     fs::create_dir_all(&project_dir).context(format!("Failed to create {}", &project_dir))?;
     deployer::ensure_env_file(&project_dir, &git_url)?;
-    deployer::write_compose_yaml(&project_dir, &host_sock)?;
-    deployer::bring_up(&project_dir)?;
+    deployer::write_compose_yaml(&project_dir, &host_sock, kind)?;
+    deployer::bring_up(runtime, &project_dir)?;
 
     println!();
 
@@ -38,83 +61,281 @@ pub fn init(project_dir: Option<String>, git_url: String) -> anyhow::Result<()>
 
     println!();
 
-    deployer::follow_deploy_logs(&project_dir, use_color)?;
+    deployer::follow_deploy_logs(runtime, use_color)?;
 
     Ok(())
 }
 
 /// Shows logs for specified containers
 pub fn logs(
-    containers: Option<Vec<String>>,
-    stacks: Option<Vec<String>>,
-    tail: u32,
-    all: bool,
+    runtime: &dyn ContainerRuntime,
+    selector: &Selector,
+    args: &LogsArgs,
 ) -> anyhow::Result<()> {
     let use_color = is_terminal();
+    let options = args.log_options()?;
 
-    let containers = if all {
-        let container_ids = list_containers()?;
+    let targets = selector.resolve_or_pick(runtime)?;
+    let containers = target_names(&targets);
 
-        if container_ids.is_empty() {
-            if use_color {
-                color_println(Color::Red, "No containers running");
-            } else {
-                println!("No containers running");
-            }
-            return Ok(());
+    if containers.is_empty() {
+        if use_color {
+            color_println(Color::Red, "No containers running");
+        } else {
+            println!("No containers running");
         }
+        return Ok(());
+    }
 
-        container_ids
-    } else if let Some(containers) = containers {
-        containers
-    } else if let Some(stacks) = stacks {
-        let mut containers = vec![];
+    if let Some(window) = args.rate {
+        return log_rates(runtime, &targets, window, use_color);
+    }
 
-        for stack in &stacks {
-            let container_names = get_containers_from_stack(stack)?;
-            containers.extend(container_names);
-        }
+    let action = if options.follow {
+        "Following"
+    } else {
+        "Showing"
+    };
+    let mut printer = LogPrinter::new(args, use_color);
+    let mut message = format!("{action} logs for {} container(s)", &containers.len());
+
+    if let Some(dir) = &args.output_dir {
+        let archive = Archive::open(dir, args.rotation_policy())?;
+        printer = printer.with_sink(Box::new(archive)).to_terminal(false);
+        message = format!(
+            "Archiving logs for {} container(s) to {}",
+            &containers.len(),
+            dir.display()
+        );
+    }
+
+    if let Some(target) = &args.syslog {
+        let syslog = Syslog::connect(SyslogTarget::parse(target)?)?;
+        message.push_str(&format!(", forwarding to syslog {target}"));
+        printer = printer.with_sink(Box::new(syslog));
+    }
+
+    if let Some(url) = &args.loki {
+        let loki = Loki::start(LokiEndpoint::parse(url)?, args.batch_policy());
+        message.push_str(&format!(", pushing to {url}"));
+        printer = printer.with_sink(Box::new(loki));
+    }
 
-        containers
+    if use_color {
+        color_println(Color::Cyan, &message);
     } else {
-        anyhow::bail!("Must specify containers, use --stacks (-s) or use --all (-a)")
+        println!("{message}");
+    }
+
+    let mut options = options;
+    let started = Instant::now();
+
+    // prints a line, stopping once it satisfies --until-match
+    let mut handle = |line: LogLine| {
+        let matched = line.output != Output::Marker
+            && args
+                .until_match
+                .as_ref()
+                .is_some_and(|re| re.is_match(&line.text));
+        printer.print(line);
+        if matched {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
     };
 
+    if args.merge {
+        // order the backlog up to now across containers, then stream from there
+        let now = Utc::now();
+        let backlog_until = options.until.map_or(now, |until| until.min(now));
+        let backlog_options = LogOptions {
+            until: Some(backlog_until),
+            ..options.clone()
+        };
+
+        let backlog = collect_sorted(runtime, &targets, &backlog_options, use_color);
+        let stopped = backlog.into_iter().any(|line| handle(line).is_break());
+
+        if stopped || !options.follow || backlog_until < now {
+            printer.finish();
+            let end = if stopped {
+                FollowEnd::Stopped
+            } else {
+                FollowEnd::Ended
+            };
+            return wait_result(args, end);
+        }
+
+        options.since = Some(now);
+        options.tail = None;
+    }
+
+    let mut follower = LogFollower::new(runtime, options.clone(), use_color);
+    for target in &targets {
+        follower.attach(target);
+    }
+
+    if let Some(timeout) = args.timeout {
+        follower = follower.deadline(started + timeout);
+    }
+
+    // an --until bound ends every stream, so there is nothing to re-attach to, and
+    // waiting for a line fails once the containers exit rather than when they restart
+    if options.follow
+        && options.until.is_none()
+        && args.until_match.is_none()
+        && let Err(e) = follower.watch(selector)
+    {
+        let message = format!("[WARN] - Not watching for restarted containers: {e}");
+        if use_color {
+            color_println(Color::Yellow, &message);
+        } else {
+            println!("{message}");
+        }
+    }
+
+    let merge_window = args.merge.then_some(args.merge_window);
+    let end = follower.run(merge_window, handle);
+    printer.finish();
+
+    wait_result(args, end)
+}
+
+/// Rates and log file size summed over the containers of a stack
+#[derive(Debug, Default)]
+struct StackRate {
+    containers: usize,
+    lines: u64,
+    bytes: u64,
+    errors: u64,
+    /// None when no container's log file could be read
+    log_size: Option<u64>,
+}
+
+/// Samples the logs of the containers and reports who logs how much, noisiest first
+fn log_rates(
+    runtime: &dyn ContainerRuntime,
+    targets: &[Target],
+    window: Duration,
+    use_color: bool,
+) -> anyhow::Result<()> {
+    let message = format!(
+        "Sampling logs of {} container(s) for {}s...",
+        targets.len(),
+        window.as_secs_f64()
+    );
     if use_color {
-        color_println(
-            Color::Cyan,
-            &format!("Following logs for {} container(s)", &containers.len()),
-        );
+        color_println(Color::Cyan, &message);
     } else {
-        println!("Following logs for {} container(s)", &containers.len());
+        println!("{message}");
     }
-    let (tx, rx) = std::sync::mpsc::channel::<String>();
-    let mut handles: Vec<std::thread::JoinHandle<()>> = vec![];
 
-    for container in containers {
-        let tx = tx.clone();
-        let is_container_id = all;
-        let handle = spawn_container_logger(&container, is_container_id, use_color, tail, tx)
-            .with_context(|| format!("Failed to spawn container logger for {container}"))?;
-        handles.push(handle);
+    let (mut rates, elapsed) = sample_rates(runtime, targets, window, use_color);
+    let seconds = elapsed.as_secs_f64().max(0.001);
+
+    let log_sizes = runtime
+        .inspect(&target_names(targets))?
+        .into_iter()
+        .filter(|i| !i.log_path.is_empty())
+        .map(|i| (i.container_name, log_file_size(&i.log_path)))
+        .collect::<HashMap<String, Option<u64>>>();
+
+    if log_sizes.values().any(Option::is_none) {
+        warn(
+            use_color,
+            "Some log files could not be read, run as root to see their size",
+        );
     }
 
-    drop(tx);
+    rates.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.container.cmp(&b.container)));
 
-    for log_line in rx {
-        println!("{log_line}");
+    let per_second = |count: u64| format!("{:.1}", count as f64 / seconds);
+    let bytes_per_second =
+        |bytes: u64| format!("{}/s", format_size((bytes as f64 / seconds) as u64));
+    let size = |size: Option<u64>| size.map_or_else(|| "--".to_string(), format_size);
+
+    let headers = |first: &str, second: &str| {
+        [first, second, "LINES/S", "BYTES/S", "ERRORS", "LOG SIZE"]
+            .map(String::from)
+            .to_vec()
+    };
+    let mut containers = Table::new(headers("NAME", "STACK")).max_width(terminal_width());
+
+    let mut stacks: BTreeMap<&str, StackRate> = BTreeMap::new();
+    for rate in &rates {
+        let log_size = log_sizes.get(&rate.container).copied().flatten();
+        let stack = rate.stack.as_deref().unwrap_or("-");
+
+        containers.row(vec![
+            rate.container.clone(),
+            stack.to_string(),
+            per_second(rate.lines),
+            bytes_per_second(rate.bytes),
+            rate.errors.to_string(),
+            size(log_size),
+        ]);
+
+        let total = stacks.entry(stack).or_default();
+        total.containers += 1;
+        total.lines += rate.lines;
+        total.bytes += rate.bytes;
+        total.errors += rate.errors;
+        if let Some(log_size) = log_size {
+            *total.log_size.get_or_insert(0) += log_size;
+        }
     }
 
-    for handle in handles {
-        let _ = handle.join();
+    let mut stacks = stacks.into_iter().collect::<Vec<_>>();
+    stacks.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+
+    let mut stack_table = Table::new(headers("STACK", "CONTAINERS")).max_width(terminal_width());
+    for (stack, total) in stacks {
+        stack_table.row(vec![
+            stack.to_string(),
+            total.containers.to_string(),
+            per_second(total.lines),
+            bytes_per_second(total.bytes),
+            total.errors.to_string(),
+            size(total.log_size),
+        ]);
     }
 
+    println!();
+    print!("{}", containers.render());
+    println!();
+    print!("{}", stack_table.render());
+
     Ok(())
 }
 
+/// Fails with a [`WaitError`] when `--until-match` is set and following ended
+/// without a matching line
+fn wait_result(args: &LogsArgs, end: FollowEnd) -> anyhow::Result<()> {
+    if args.until_match.is_none() {
+        return Ok(());
+    }
+
+    let error = match end {
+        FollowEnd::Stopped => return Ok(()),
+        FollowEnd::TimedOut => WaitError::TimedOut(args.timeout.unwrap_or_default()),
+        FollowEnd::Ended => WaitError::Ended,
+        FollowEnd::Interrupted => WaitError::Interrupted,
+    };
+
+    Err(error.into())
+}
+
 /// Kills all running containers, and then redeploys docker-stack-deploy
-pub fn nuke(project_dir: Option<String>) -> anyhow::Result<()> {
-    let project_dir = project_dir.unwrap_or_else(deployer::default_project_dir);
+///
+/// The confirmation is read from `input`, usually stdin.
+pub fn nuke(
+    runtime: &dyn ContainerRuntime,
+    kind: RuntimeKind,
+    project_dir: Option<String>,
+    input: &mut dyn BufRead,
+) -> anyhow::Result<()> {
+    let project_dir = project_dir.unwrap_or_else(|| deployer::default_project_dir(kind.detect()));
 
     // ask user to confirm action
     color_println(
@@ -129,9 +350,9 @@ pub fn nuke(project_dir: Option<String>) -> anyhow::Result<()> {
     let _ = io::stdout().flush();
 
     // capture user input
-    let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
-    let response = input.trim().to_lowercase();
+    let mut answer = String::new();
+    let _ = input.read_line(&mut answer);
+    let response = answer.trim().to_lowercase();
 
     // evaluate response
     match response.as_str() {
@@ -145,281 +366,224 @@ pub fn nuke(project_dir: Option<String>) -> anyhow::Result<()> {
     };
 
     // get list of currently running docker containers by id
-    let container_ids = list_containers()?;
+    let container_ids = list_containers(runtime)?;
 
     // if docker containers are running, kill them
     if container_ids.is_empty() {
         color_println(Color::Red, "No containers running");
         return Ok(());
     } else {
-        kill_containers(container_ids)?
+        kill_containers(runtime, container_ids)?
     }
 
     color_println(Color::Green, "Running docker-stack-deploy...");
 
-    deployer::bring_up(&project_dir)?;
+    deployer::bring_up(runtime, &project_dir)?;
 
     color_println(
         Color::Green,
         "Following logs until all containers deployed...",
     );
 
-    deployer::follow_deploy_logs(&project_dir, true)?;
+    deployer::follow_deploy_logs(runtime, true)?;
 
     Ok(())
 }
 
 /// Restarts specified docker containers
 pub fn restart(
-    containers: Option<Vec<String>>,
-    stacks: Option<Vec<String>>,
-    all: bool,
+    runtime: &dyn ContainerRuntime,
+    selector: &Selector,
+    output: OutputFormat,
 ) -> anyhow::Result<()> {
-    let containers = if all {
-        list_containers()?
-    } else if let Some(containers) = containers {
-        containers
-    } else if let Some(stacks) = stacks {
-        let mut containers = vec![];
-
-        for stack in &stacks {
-            let container_names = get_containers_from_stack(stack)?;
-            containers.extend(container_names);
-        }
-
-        containers
-    } else {
-        anyhow::bail!("Must specify containers, use --stacks (-s) or use --all (-a)")
-    };
+    let use_color = is_terminal() && !output.is_structured();
+    let containers = target_names(&selector.resolve_or_pick(runtime)?);
 
-    let use_color = is_terminal();
-
-    for container in &containers {
+    if containers.is_empty() {
+        if output.is_structured() {
+            return emit::<RestartResult>(&mut io::stdout(), output, &[]);
+        }
         if use_color {
-            color_println(
-                Color::Cyan,
-                &format!("Restarting container: {}", &container),
-            );
+            color_println(Color::Red, "No containers running");
         } else {
-            println!("Restarting container: {}", &container)
+            println!("No containers running");
         }
+        return Ok(());
+    }
+
+    let mut results = Vec::with_capacity(containers.len());
+    for container in &containers {
+        if !output.is_structured() {
+            if use_color {
+                color_println(
+                    Color::Cyan,
+                    &format!("Restarting container: {}", &container),
+                );
+            } else {
+                println!("Restarting container: {}", &container)
+            }
+        }
+
+        let result = restart_container(runtime, container);
+        if let (Some(error), false) = (&result.error, output.is_structured()) {
+            warn(use_color, error);
+        }
+        results.push(result);
+    }
 
-        Command::new(DOCKER)
-            .args(["restart", container])
-            .status()
-            .context(format!("Failed to restart {}", &container))?;
+    if output.is_structured() {
+        emit(&mut io::stdout(), output, &results)?;
+    }
+
+    let failed = results.iter().filter(|r| !r.restarted).count();
+    if failed > 0 {
+        return Err(PartialFailure {
+            action: "restart",
+            failed,
+        }
+        .into());
     }
 
     Ok(())
 }
 
-/// Container stats to be gathered
-#[derive(Debug, Clone)]
-struct ContainerStats {
-    name: String,
-    status: String,
-    health: String,
-    restart_policy: String,
-    uptime: String,
-    cpu_usage: String,
-    memory_usage: String,
-    ports: String,
+/// Prints a warning to stderr
+fn warn(use_color: bool, text: &str) {
+    if use_color {
+        eprintln!(
+            "{}",
+            color_println_fmt(Color::Yellow, &format!("warning: {text}"))
+        );
+    } else {
+        eprintln!("warning: {text}");
+    }
 }
 
 /// View stats for docker containers
 pub fn stats(
-    containers: Option<Vec<String>>,
-    stacks: Option<Vec<String>>,
-    all: bool,
+    runtime: &dyn ContainerRuntime,
+    selector: &Selector,
+    args: &StatsArgs,
+    output: OutputFormat,
+    out: &mut dyn Write,
 ) -> anyhow::Result<()> {
-    let use_color = is_terminal();
-    let containers = if all {
-        let container_ids = list_containers()?;
+    if args.watch && output.is_structured() {
+        bail!("--watch only supports text output");
+    }
+    if args.group_by.is_some() && output.is_structured() {
+        bail!("--group-by only supports text output");
+    }
 
-        if container_ids.is_empty() {
+    let use_color = is_terminal() && !output.is_structured();
+    let selector = selector.clone().with_stopped(args.include_stopped);
+    let containers = target_names(&selector.resolve_or_pick(runtime)?);
+
+    if containers.is_empty() && output.is_structured() {
+        return emit::<ContainerStats>(out, output, &[]);
+    }
+    if containers.is_empty() {
+        let message = if args.include_stopped {
+            "No containers found"
+        } else {
+            "No containers running"
+        };
+        if use_color {
+            writeln!(out, "{}", color_println_fmt(Color::Red, message))?;
+        } else {
+            writeln!(out, "{message}")?;
+        }
+        return Ok(());
+    }
+
+    if args.watch {
+        return watch_stats(runtime, &containers, args, use_color);
+    }
+
+    let (mut rows, warnings) = collect_stats(runtime, &containers)?;
+    sort_rows(&mut rows, args.sort, args.reverse);
+    for warning in warnings {
+        warn(use_color, &warning);
+    }
+
+    if args.problems {
+        rows.retain(|row| row.is_problem(args.max_restarts));
+        if rows.is_empty() && !output.is_structured() {
             if use_color {
-                color_println(Color::Red, "No containers running");
+                writeln!(
+                    out,
+                    "{}",
+                    color_println_fmt(Color::Green, "No problems found")
+                )?;
             } else {
-                println!("No containers running");
+                writeln!(out, "No problems found")?;
             }
             return Ok(());
         }
+    }
 
-        container_ids
-    } else if let Some(containers) = containers {
-        containers
-    } else if let Some(stacks) = stacks {
-        let mut containers = vec![];
+    if output.is_structured() {
+        return emit(out, output, &rows);
+    }
 
-        for stack in &stacks {
-            let container_names = get_containers_from_stack(stack)?;
-            containers.extend(container_names);
-        }
+    write!(out, "{}", render_table(&rows, None, args, use_color))?;
 
-        containers
-    } else {
-        anyhow::bail!("Must specify containers, use --stacks (-s) or use --all (-a)")
-    };
+    Ok(())
+}
 
-    // stats format from docker cli
-    let stats_output = Command::new(DOCKER)
-        .args([
-            "stats",
-            "--no-stream",
-            "--format",
-            "table {{.Name}}\t{{.CPUPerc}}\t{{.MemPerc}}",
-        ])
-        .args(&containers)
-        .output()
-        .context("Failed to get stats for containers")?;
-
-    // inspect format from docker cli
-    let inspect_format = concat!(
-        "{{.Name}},",
-        "{{.State.Status}},",
-        "{{if .HostConfig.RestartPolicy}}{{if .HostConfig.RestartPolicy.Name}}{{.HostConfig.RestartPolicy.Name}}{{else}}no{{end}}{{else}}no{{end}},",
-        "{{if index .State \"Health\"}}{{.State.Health.Status}}{{else}}N/A{{end}},",
-        "{{.State.StartedAt}},",
-        "{{if .NetworkSettings.Ports}}{{range $key, $value := .NetworkSettings.Ports}}{{$key}}{{if $value}}:{{(index $value 0).HostPort}}{{end}} {{end}}{{else}}N/A{{end}}"
-        );
+/// Redraws the stats table every `interval` until Ctrl-C is pressed, on the
+/// terminal's alternate screen when stdout is one
+fn watch_stats(
+    runtime: &dyn ContainerRuntime,
+    containers: &[String],
+    args: &StatsArgs,
+    use_color: bool,
+) -> anyhow::Result<()> {
+    let interval = args.interval;
+    catch_interrupts();
+    // restores the terminal when dropped, also when refreshing fails
+    let screen = use_color.then(AlternateScreen::enter);
+    let mut previous: Option<Vec<ContainerStats>> = None;
+
+    while !interrupted() {
+        let started = Instant::now();
+
+        let (mut rows, warnings) = match collect_stats(runtime, containers) {
+            Ok(collected) => collected,
+            // Ctrl-C also reaches a `docker stats` that was running
+            Err(_) if interrupted() => break,
+            Err(e) => return Err(e),
+        };
 
-    let inspect_output = Command::new(DOCKER)
-        .arg("inspect")
-        .args(&containers)
-        .args(["--format", inspect_format])
-        .output()
-        .context("Failed to inspect containers")?;
-
-    let stats_string = String::from_utf8(stats_output.stdout)?;
-    let inspect_string = String::from_utf8(inspect_output.stdout)?;
-
-    let mut temp_stats_map: HashMap<String, StatsData> = HashMap::new();
-    let mut temp_inspect_map: HashMap<String, InspectData> = HashMap::new();
-
-    // skip header line
-    for line in stats_string.lines().skip(1) {
-        let parsed = parse_stats_data(line)?;
-        temp_stats_map.insert(
-            parsed.container_name.clone(),
-            StatsData {
-                container_name: parsed.container_name,
-                cpu: parsed.cpu,
-                memory: parsed.memory,
-            },
-        );
-    }
+        sort_rows(&mut rows, args.sort, args.reverse);
+        if args.problems {
+            rows.retain(|row| row.is_problem(args.max_restarts));
+        }
 
-    for line in inspect_string.lines() {
-        let parsed = parse_inspect_data(line)?;
-        temp_inspect_map.insert(
-            parsed.container_name.clone(),
-            InspectData {
-                container_name: parsed.container_name,
-                status: parsed.status,
-                restart_policy: parsed.restart_policy,
-                health: parsed.health,
-                uptime: parsed.uptime,
-                ports: parsed.ports,
-            },
+        let mut frame = format!(
+            "Every {}s: dsd-util stats    {}\n\n",
+            interval.as_secs_f64(),
+            Local::now().format("%Y-%m-%d %H:%M:%S")
         );
-    }
-
-    assert_eq!(&temp_stats_map.len(), &temp_inspect_map.len());
-
-    let mut total_stats_map: HashMap<String, ContainerStats> = HashMap::new();
-
-    for key in temp_stats_map.keys() {
-        let stats = temp_stats_map
-            .get(key)
-            .with_context(|| format!("Failed to get stats for {key}"))?;
-        let inspect = temp_inspect_map
-            .get(key)
-            .with_context(|| format!("Failed to get stats for {key}"))?;
-
-        let container_stats = if use_color {
-            ContainerStats {
-                name: color_println_fmt(Color::Cyan, &stats.container_name),
-                status: {
-                    if &inspect.status.to_lowercase() == "running" {
-                        color_println_fmt(Color::Green, &inspect.status)
-                    } else if &inspect.status.to_lowercase() == "created" {
-                        color_println_fmt(Color::Cyan, &inspect.status)
-                    } else if &inspect.status.to_lowercase() == "paused"
-                        || &inspect.status.to_lowercase() == "restarting"
-                    {
-                        color_println_fmt(Color::Yellow, &inspect.status)
-                    } else {
-                        color_println_fmt(Color::Red, &inspect.status)
-                    }
-                },
-                restart_policy: inspect.restart_policy.to_string(),
-                health: {
-                    if &inspect.health.to_lowercase() == "healthy" {
-                        color_println_fmt(Color::Green, &inspect.health)
-                    } else if &inspect.health.to_lowercase() == "unhealthy" {
-                        color_println_fmt(Color::Red, &inspect.health)
-                    } else if &inspect.health.to_lowercase() == "starting" {
-                        color_println_fmt(Color::Cyan, &inspect.health)
-                    } else {
-                        color_println_fmt(Color::White, &inspect.health)
-                    }
-                },
-                uptime: inspect.uptime.to_string(),
-                cpu_usage: stats.cpu.to_string(),
-                memory_usage: stats.memory.to_string(),
-                ports: inspect.ports.to_string(),
-            }
-        } else {
-            ContainerStats {
-                name: stats.container_name.to_string(),
-                status: inspect.status.to_string(),
-                restart_policy: inspect.restart_policy.to_string(),
-                health: inspect.health.to_string(),
-                uptime: inspect.uptime.to_string(),
-                cpu_usage: stats.cpu.to_string(),
-                memory_usage: stats.memory.to_string(),
-                ports: inspect.ports.to_string(),
+        frame.push_str(&render_table(&rows, previous.as_deref(), args, use_color));
+        for warning in warnings {
+            let warning = format!("warning: {warning}");
+            if use_color {
+                frame.push_str(&format!("\n{}", color_println_fmt(Color::Yellow, &warning)));
+            } else {
+                frame.push_str(&format!("\n{warning}"));
             }
-        };
-
-        total_stats_map.insert(key.to_string(), container_stats);
-    }
-    if use_color {
-        println!(
-            "{:<35} {:<20} {:<16} {:<20} {:<18} {:<8} {:<8} {:<20}",
-            &color_println_fmt(Color::White, "NAME"),
-            &color_println_fmt(Color::White, "STATUS"),
-            "RESTART",
-            &color_println_fmt(Color::White, "HEALTH"),
-            "UPTIME",
-            "CPU %",
-            "MEM %",
-            "PORTS"
-        );
-    } else {
-        println!(
-            "{:<35} {:<20} {:<16} {:<20} {:<18} {:<8} {:<8} {:<20}",
-            "NAME", "STATUS", "RESTART", "HEALTH", "UPTIME", "CPU %", "MEM %", "PORTS"
-        );
-    }
-
-    println!();
+        }
 
-    // TODO: sort - probably want to use BTreeMap instead
-    for key in total_stats_map.keys() {
-        let container = total_stats_map.get(key).context("Failed to get item")?;
+        match &screen {
+            Some(screen) => screen.redraw(&frame),
+            None => println!("{frame}"),
+        }
+        previous = Some(rows);
 
-        println!(
-            "{:<35} {:<20} {:<16} {:<20} {:<18} {:<8} {:<8} {:<20}",
-            container.name,
-            container.status,
-            container.restart_policy,
-            container.health,
-            container.uptime,
-            container.cpu_usage,
-            container.memory_usage,
-            container.ports
-        );
+        while !interrupted() && started.elapsed() < interval {
+            let remaining = interval.saturating_sub(started.elapsed());
+            std::thread::sleep(remaining.min(WATCH_POLL_INTERVAL));
+        }
     }
 
     Ok(())
@@ -427,46 +591,62 @@ pub fn stats(
 
 /// Updates images of specified docker containers
 pub fn update(
-    containers: Option<Vec<String>>,
-    stacks: Option<Vec<String>>,
-    all: bool,
+    runtime: &dyn ContainerRuntime,
+    selector: &Selector,
+    output: OutputFormat,
 ) -> anyhow::Result<()> {
-    let containers = if all {
-        list_containers()?
-    } else if let Some(containers) = containers {
-        containers
-    } else if let Some(stacks) = stacks {
-        let mut containers = vec![];
-
-        for stack in &stacks {
-            let container_names = get_containers_from_stack(stack)?;
-            containers.extend(container_names);
+    let use_color = is_terminal() && !output.is_structured();
+    let containers = target_names(&selector.resolve_or_pick(runtime)?);
+
+    if containers.is_empty() {
+        if output.is_structured() {
+            return emit::<UpdateResult>(&mut io::stdout(), output, &[]);
+        }
+        if use_color {
+            color_println(Color::Red, "No containers running");
+        } else {
+            println!("No containers running");
         }
+        return Ok(());
+    }
 
-        containers
-    } else {
-        anyhow::bail!("Must specify containers, use --stacks (-s) or use --all (-a)")
+    // progress goes to stderr when stdout carries the results
+    let mut progress = |progress: UpdateProgress| match progress {
+        UpdateProgress::Pulling { container, image } => {
+            let message = format!("Pulling image for {container}: {image}");
+            if output.is_structured() {
+                eprintln!("{message}");
+            } else if use_color {
+                color_println(Color::Cyan, &message);
+            } else {
+                println!("{message}");
+            }
+        }
+        UpdateProgress::Message(line) if output.is_structured() => eprintln!("{line}"),
+        UpdateProgress::Message(line) => println!("{line}"),
     };
 
-    let use_color = is_terminal();
-
-    let mut num_containers_updated = 0;
-
+    let mut results = Vec::with_capacity(containers.len());
     for container in &containers {
-        num_containers_updated += update_container_by_name(container)?;
+        let result = update_container_by_name(runtime, container, &mut progress);
+        if let Some(error) = &result.error {
+            warn(use_color, error);
+        }
+        results.push(result);
     }
 
-    if num_containers_updated == 0 {
+    let num_containers_updated = results.iter().filter(|r| r.updated).count();
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+
+    if output.is_structured() {
+        emit(&mut io::stdout(), output, &results)?;
+    } else if num_containers_updated == 0 {
         if use_color {
             color_println(Color::Yellow, "No new container images to update");
         } else {
             println!("No new container images to pull");
         }
-
-        return Ok(());
-    }
-
-    if use_color {
+    } else if use_color {
         println!(
             "{}: {}",
             &color_println_fmt(Color::Cyan, "New images pulled"),
@@ -480,11 +660,321 @@ pub fn update(
         println!("Restarting {DSD}");
     }
 
-    // containers updated, restart docker-stack-deploy to deploy new image
-    Command::new(DOCKER)
-        .args(["restart", DSD])
-        .status()
-        .context(format!("Failed to restart {DSD}"))?;
+    if num_containers_updated > 0 {
+        // containers updated, restart docker-stack-deploy to deploy new image
+        runtime
+            .restart(DSD)
+            .context(format!("Failed to restart {DSD}"))?;
+    }
+
+    if failed > 0 {
+        return Err(PartialFailure {
+            action: "update",
+            failed,
+        }
+        .into());
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{FakeCall, FakeContainer, FakeRuntime};
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct StatsCli {
+        #[command(flatten)]
+        args: StatsArgs,
+    }
+
+    fn stats_args(args: &[&str]) -> StatsArgs {
+        StatsCli::parse_from(std::iter::once("stats").chain(args.iter().copied())).args
+    }
+
+    fn select(containers: &[&str]) -> Selector {
+        Selector {
+            containers: containers.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn web() -> FakeContainer {
+        let mut web = FakeContainer::new("aaa", "web")
+            .stack("media")
+            .image("nginx");
+        web.stats.cpu = Some(12.5);
+        web.stats.memory = Some(3.0);
+        web.stats.memory_bytes = Some(1024);
+        web
+    }
+
+    #[test]
+    fn collect_stats_samples_running_containers_only() {
+        let runtime = FakeRuntime::new(vec![web(), FakeContainer::new("bbb", "old").stopped()]);
+
+        let (rows, warnings) =
+            collect_stats(&runtime, &["web".to_string(), "old".to_string()]).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].name, "web");
+        assert_eq!(rows[0].stack.as_deref(), Some("media"));
+        assert_eq!(rows[0].cpu_usage, Some(12.5));
+        assert_eq!(rows[0].memory_bytes, Some(1024));
+        assert_eq!(rows[1].name, "old");
+        assert_eq!(rows[1].status, "exited");
+        assert_eq!(rows[1].cpu_usage, None);
+    }
+
+    #[test]
+    fn collect_stats_warns_about_vanished_containers() {
+        let runtime = FakeRuntime::new(vec![web()]);
+
+        let (rows, warnings) =
+            collect_stats(&runtime, &["web".to_string(), "gone".to_string()]).unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(warnings, ["Container gone not found, skipping"]);
+    }
+
+    #[test]
+    fn sort_rows_orders_by_stack_then_name() {
+        let runtime = FakeRuntime::new(vec![
+            FakeContainer::new("aaa", "zeta").stack("apps"),
+            FakeContainer::new("bbb", "loose"),
+            FakeContainer::new("ccc", "alpha").stack("media"),
+            FakeContainer::new("ddd", "beta").stack("apps"),
+        ]);
+        let names = ["zeta", "loose", "alpha", "beta"].map(String::from);
+
+        let (mut rows, _) = collect_stats(&runtime, &names).unwrap();
+        sort_rows(&mut rows, None, false);
+
+        let sorted = rows.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(sorted, ["beta", "zeta", "alpha", "loose"]);
+    }
+
+    #[test]
+    fn nuke_removes_running_containers_once_confirmed() {
+        let runtime = FakeRuntime::new(vec![
+            web(),
+            FakeContainer::new("bbb", "db"),
+            FakeContainer::new("ccc", "old").stopped(),
+        ]);
+
+        nuke(
+            &runtime,
+            RuntimeKind::Docker,
+            Some("/srv/dsd".to_string()),
+            &mut "y\n".as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            runtime.calls(),
+            [
+                FakeCall::Remove(vec!["aaa".to_string(), "bbb".to_string()]),
+                FakeCall::ComposeUp("/srv/dsd/compose.yml".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn nuke_does_nothing_unless_confirmed() {
+        let runtime = FakeRuntime::new(vec![web()]);
+
+        for answer in ["n\n", "\n", "nope\n", ""] {
+            nuke(
+                &runtime,
+                RuntimeKind::Docker,
+                Some("/srv/dsd".to_string()),
+                &mut answer.as_bytes(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(runtime.calls(), []);
+    }
+
+    #[test]
+    fn stats_prints_structured_rows() {
+        let mut db = FakeContainer::new("bbb", "db").image("postgres");
+        db.inspect.restart_count = 7;
+        db.inspect.status = "restarting".to_string();
+        let runtime = FakeRuntime::new(vec![web(), db]);
+
+        let mut json = vec![];
+        stats(
+            &runtime,
+            &select(&["web", "db"]),
+            &stats_args(&[]),
+            OutputFormat::Json,
+            &mut json,
+        )
+        .unwrap();
+
+        let records: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        let records = records.as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        let web = records.iter().find(|r| r["name"] == "web").unwrap();
+        assert_eq!(web["stack"], "media");
+        assert_eq!(web["image"], "nginx");
+        assert_eq!(web["cpu_usage"], 12.5);
+        assert_eq!(web["memory_usage"], 3.0);
+        assert_eq!(web["memory_bytes"], 1024);
+        assert_eq!(web["net_rx"], serde_json::Value::Null);
+
+        let mut csv = vec![];
+        stats(
+            &runtime,
+            &select(&["web", "db"]),
+            &stats_args(&["--problems"]),
+            OutputFormat::Csv,
+            &mut csv,
+        )
+        .unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("name,stack,status,health,restart_policy,"));
+        let row = lines[1].split(',').collect::<Vec<_>>();
+        let column = |name: &str| {
+            let index = lines[0].split(',').position(|h| h == name).unwrap();
+            row[index]
+        };
+        assert_eq!(column("name"), "db");
+        assert_eq!(column("stack"), "");
+        assert_eq!(column("status"), "restarting");
+        assert_eq!(column("restart_count"), "7");
+        assert_eq!(column("image"), "postgres");
+    }
+
+    #[test]
+    fn stats_rejects_watching_structured_output() {
+        let runtime = FakeRuntime::new(vec![web()]);
+
+        let error = stats(
+            &runtime,
+            &select(&["web"]),
+            &stats_args(&["--watch"]),
+            OutputFormat::Json,
+            &mut vec![],
+        )
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "--watch only supports text output");
+    }
+
+    #[test]
+    fn stats_rejects_grouping_structured_output() {
+        let runtime = FakeRuntime::new(vec![web()]);
+
+        let error = stats(
+            &runtime,
+            &select(&["web"]),
+            &stats_args(&["--group-by", "stack"]),
+            OutputFormat::Json,
+            &mut vec![],
+        )
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "--group-by only supports text output");
+    }
+
+    #[test]
+    fn update_pulls_images_and_restarts_the_deployer() {
+        let runtime = FakeRuntime::new(vec![
+            web().with_update(),
+            FakeContainer::new("bbb", "db").image("postgres"),
+            FakeContainer::new("ccc", DSD),
+        ]);
+
+        update(&runtime, &select(&["web", "db"]), OutputFormat::Text).unwrap();
+
+        assert_eq!(
+            runtime.calls(),
+            [
+                FakeCall::Pull("nginx".to_string()),
+                FakeCall::Pull("postgres".to_string()),
+                FakeCall::Restart(DSD.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn update_without_new_images_leaves_the_deployer_running() {
+        let runtime = FakeRuntime::new(vec![web(), FakeContainer::new("ccc", DSD)]);
+
+        update(&runtime, &select(&["web"]), OutputFormat::Json).unwrap();
+
+        assert_eq!(runtime.calls(), [FakeCall::Pull("nginx".to_string())]);
+    }
+
+    #[test]
+    fn update_container_reports_the_pulled_digest() {
+        let runtime = FakeRuntime::new(vec![web().with_update()]);
+        let mut pulling = vec![];
+
+        let result = update_container_by_name(&runtime, "web", &mut |progress| {
+            if let UpdateProgress::Pulling { container, image } = progress {
+                pulling.push(format!("{container}: {image}"));
+            }
+        });
+
+        assert_eq!(pulling, ["web: nginx"]);
+        assert_eq!(result.image, "nginx");
+        assert!(result.updated);
+        assert_eq!(result.digest.as_deref(), Some("sha256:nginx"));
+        assert_eq!(result.error, None);
+    }
+
+    #[test]
+    fn update_container_records_a_failed_inspect() {
+        let runtime = FakeRuntime::new(vec![]);
+
+        let result = update_container_by_name(&runtime, "gone", &mut |_| {});
+
+        assert!(!result.updated);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Failed to inspect container gone")
+        );
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[test]
+    fn restart_restarts_every_selected_container() {
+        let runtime = FakeRuntime::new(vec![
+            web(),
+            FakeContainer::new("bbb", "db"),
+            FakeContainer::new("ccc", "cache"),
+        ]);
+
+        restart(&runtime, &select(&["web", "db"]), OutputFormat::Yaml).unwrap();
+
+        assert_eq!(
+            runtime.calls(),
+            [
+                FakeCall::Restart("web".to_string()),
+                FakeCall::Restart("db".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn restart_container_records_the_failure() {
+        let runtime = FakeRuntime::new(vec![]);
+
+        let result = restart_container(&runtime, "gone");
+
+        assert!(!result.restarted);
+        assert_eq!(
+            result.error.as_deref(),
+            Some("Failed to restart gone: No such container: gone")
+        );
+    }
+}
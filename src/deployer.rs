@@ -1,41 +1,90 @@
 // Reference to compose file in the docker-stack-deploy repo:
 // https://github.com/wez/docker-stack-deploy/blob/main/compose.yml
 
-use crate::commands::DOCKER;
+use crate::logs::{LogFollower, Output};
 use crate::printer::{color_println_fmt, Color};
+use crate::runtime::{ContainerRuntime, LogOptions, RuntimeKind};
+use crate::selector::Target;
 use crate::utils::get_timestamp;
 use anyhow::Context;
+use chrono::Utc;
+use regex::Regex;
 use serde::Serialize;
 use std::collections::BTreeMap;
 use std::fs;
-use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::io::{IsTerminal, Write};
+use std::ops::ControlFlow;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
-use std::process::{Command, Stdio};
 
 const DSD: &str = "docker-stack-deploy";
 
-/// Resolves the docker host socket path from DOCKER_HOST or falls back to the default
-pub fn resolve_host_sock() -> anyhow::Result<String> {
-    match std::env::var("DOCKER_HOST") {
-        Ok(s) => {
-            let Some(path) = s.strip_prefix("unix://") else {
-                anyhow::bail!("dsd-util only supports unix:// DOCKER_HOST. Got: {s}");
-            };
-            Ok(path.to_string())
-        }
-        Err(_) => Ok("/var/run/docker.sock".to_string()),
+/// Logged by the deployer once it checked the git remote for changes
+const UP_TO_DATE_PATTERN: &str = "Already up to date";
+
+/// Default socket of a rootful docker daemon
+pub const DOCKER_SOCK: &str = "/var/run/docker.sock";
+
+/// Default socket of the rootful podman service
+pub const PODMAN_SOCK: &str = "/run/podman/podman.sock";
+
+/// Resolves the engine socket path for the given runtime
+///
+/// Docker uses DOCKER_HOST, podman uses CONTAINER_HOST, and both fall back to their
+/// default socket locations.
+pub fn resolve_host_sock(kind: RuntimeKind) -> anyhow::Result<String> {
+    match kind {
+        RuntimeKind::Podman => resolve_podman_sock(
+            std::env::var("CONTAINER_HOST").ok().as_deref(),
+            std::env::var("XDG_RUNTIME_DIR").ok().as_deref(),
+            |path| Path::new(path).exists(),
+        ),
+        _ => match std::env::var("DOCKER_HOST") {
+            Ok(s) => strip_unix_scheme("DOCKER_HOST", &s),
+            Err(_) => Ok(DOCKER_SOCK.to_string()),
+        },
+    }
+}
+
+/// Pure resolver for the podman socket — takes explicit env slices and an existence check
+/// so it's testable without touching the host.
+///
+/// Prefers the rootless user socket under $XDG_RUNTIME_DIR, then the rootful service socket.
+fn resolve_podman_sock(
+    container_host: Option<&str>,
+    xdg_runtime_dir: Option<&str>,
+    exists: impl Fn(&str) -> bool,
+) -> anyhow::Result<String> {
+    if let Some(host) = container_host.filter(|s| !s.is_empty()) {
+        return strip_unix_scheme("CONTAINER_HOST", host);
+    }
+
+    let user_sock = xdg_runtime_dir
+        .filter(|s| !s.is_empty())
+        .map(|dir| format!("{dir}/podman/podman.sock"));
+
+    match user_sock {
+        Some(user_sock) if exists(&user_sock) || !exists(PODMAN_SOCK) => Ok(user_sock),
+        _ => Ok(PODMAN_SOCK.to_string()),
     }
 }
 
-/// Default project_dir: user-level XDG path when docker appears user-scoped
-/// (any unix:// DOCKER_HOST other than the default rootful sock), /var/lib otherwise.
+/// Extracts the socket path from a unix:// host variable
+fn strip_unix_scheme(var: &str, value: &str) -> anyhow::Result<String> {
+    let Some(path) = value.strip_prefix("unix://") else {
+        anyhow::bail!("dsd-util only supports unix:// {var}. Got: {value}");
+    };
+    Ok(path.to_string())
+}
+
+/// Default project_dir: user-level XDG path when the runtime appears user-scoped
+/// (any unix:// socket other than the default rootful docker or podman sock), /var/lib otherwise.
 ///
 /// This covers rootless setups regardless of whether the socket lives under
 /// /run/user/ (systemd), $XDG_RUNTIME_DIR elsewhere, or a user-chosen path.
-pub fn default_project_dir() -> String {
+pub fn default_project_dir(kind: RuntimeKind) -> String {
     compute_default_project_dir(
-        std::env::var("DOCKER_HOST").ok().as_deref(),
+        resolve_host_sock(kind).ok().as_deref(),
         std::env::var("XDG_DATA_HOME").ok().as_deref(),
         std::env::var("HOME").unwrap_or_default().as_str(),
     )
@@ -44,15 +93,12 @@ pub fn default_project_dir() -> String {
 /// Pure resolver for default_project_dir — takes explicit env slices so it's testable
 /// without mutating process-global env.
 fn compute_default_project_dir(
-    docker_host: Option<&str>,
+    host_sock: Option<&str>,
     xdg_data_home: Option<&str>,
     home: &str,
 ) -> String {
-    let is_user_scoped = match docker_host {
-        Some(v) => match v.strip_prefix("unix://") {
-            Some(path) => !path.is_empty() && path != "/var/run/docker.sock",
-            None => false,
-        },
+    let is_user_scoped = match host_sock {
+        Some(path) => !path.is_empty() && path != DOCKER_SOCK && path != PODMAN_SOCK,
         None => false,
     };
 
@@ -94,19 +140,40 @@ struct DeployerService {
     env_file: &'static str,
     environment: Vec<String>,
     volumes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    security_opt: Vec<&'static str>,
 }
 
 /// Serializes the deployer compose.yml as a YAML string
-pub fn render_compose_yaml(project_dir: &str, host_sock: &str) -> anyhow::Result<String> {
+///
+/// On podman the project dir is relabeled for SELinux and label confinement is disabled,
+/// since the podman socket can't be relabeled for the container to reach it.
+pub fn render_compose_yaml(
+    project_dir: &str,
+    host_sock: &str,
+    kind: RuntimeKind,
+) -> anyhow::Result<String> {
     let environment = vec![
         format!("STACK_REPO_DIR={project_dir}/repo"),
         format!("DOCKER_SOCK_HOST={host_sock}"),
     ];
 
-    let volumes = vec![
-        format!("{host_sock}:/var/run/docker.sock"),
-        format!("{project_dir}:{project_dir}"),
-    ];
+    let (volumes, security_opt) = match kind {
+        RuntimeKind::Podman => (
+            vec![
+                format!("{host_sock}:/var/run/docker.sock"),
+                format!("{project_dir}:{project_dir}:z"),
+            ],
+            vec!["label=disable"],
+        ),
+        _ => (
+            vec![
+                format!("{host_sock}:/var/run/docker.sock"),
+                format!("{project_dir}:{project_dir}"),
+            ],
+            vec![],
+        ),
+    };
 
     let mut services = BTreeMap::new();
     services.insert(
@@ -119,6 +186,7 @@ pub fn render_compose_yaml(project_dir: &str, host_sock: &str) -> anyhow::Result
             env_file: ".env",
             environment,
             volumes,
+            security_opt,
         },
     );
 
@@ -131,9 +199,13 @@ pub fn render_compose_yaml(project_dir: &str, host_sock: &str) -> anyhow::Result
 }
 
 /// Writes the deployer compose.yml to disk, overwriting any existing file
-pub fn write_compose_yaml(project_dir: &str, host_sock: &str) -> anyhow::Result<()> {
+pub fn write_compose_yaml(
+    project_dir: &str,
+    host_sock: &str,
+    kind: RuntimeKind,
+) -> anyhow::Result<()> {
     let path = compose_path(project_dir);
-    let yaml = render_compose_yaml(project_dir, host_sock)?;
+    let yaml = render_compose_yaml(project_dir, host_sock, kind)?;
 
     fs::write(&path, yaml).context(format!("Failed to write {}", &path))?;
 
@@ -204,65 +276,111 @@ fn prompt_secret(key: &str) -> anyhow::Result<String> {
     Ok(value)
 }
 
-/// Brings up the deployer container via `docker compose up -d`
-pub fn bring_up(project_dir: &str) -> anyhow::Result<()> {
-    let status = Command::new(DOCKER)
-        .args(["compose", "-f", &compose_path(project_dir), "up", "-d"])
-        .status()
-        .context("Failed to start docker-stack-deploy")?;
-
-    if !status.success() {
-        anyhow::bail!("docker compose up -d failed with status {status}");
-    }
-
-    Ok(())
+/// Brings up the deployer container via `docker compose up -d` (or its podman equivalent)
+pub fn bring_up(runtime: &dyn ContainerRuntime, project_dir: &str) -> anyhow::Result<()> {
+    runtime.compose_up(&compose_path(project_dir))
 }
 
 /// Follows deployer logs until the first "Already up to date" line after deploy
-pub fn follow_deploy_logs(project_dir: &str, use_color: bool) -> anyhow::Result<()> {
-    let start_time = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .context("Failed to get current time")?
-        .as_secs();
+pub fn follow_deploy_logs(runtime: &dyn ContainerRuntime, use_color: bool) -> anyhow::Result<()> {
+    let up_to_date = Regex::new(UP_TO_DATE_PATTERN).context("Invalid deploy wait pattern")?;
+    let options = LogOptions {
+        since: Some(Utc::now()),
+        follow: true,
+        ..Default::default()
+    };
 
     // follow docker-stack-deploy logs until first update check has happened
-    let mut logs_process = Command::new(DOCKER)
-        .args([
-            "compose",
-            "-f",
-            &compose_path(project_dir),
-            "logs",
-            "--follow",
-            "--no-log-prefix",
-            "--since",
-            &start_time.to_string(),
-        ])
-        .stdout(Stdio::piped())
-        .spawn()
-        .context("Failed to start following logs")?;
-
-    if let Some(stdout) = logs_process.stdout.take() {
-        let reader = BufReader::new(stdout);
-        for (i, line) in reader.lines().map_while(Result::ok).enumerate() {
-            if use_color {
-                println!(
-                    "[{} | {}] {}",
-                    color_println_fmt(Color::Cyan, &get_timestamp()),
-                    color_println_fmt(Color::Magenta, DSD),
-                    line
-                );
-            } else {
-                println!("[{} | {}] {}", &get_timestamp(), DSD, line);
-            }
-            if line.contains("Already up to date") && i > 0 {
-                // first update check has happened after deployment
-                break;
-            }
+    let mut follower = LogFollower::new(runtime, options, use_color);
+    follower.attach(&Target {
+        id: DSD.to_string(),
+        name: DSD.to_string(),
+        stack: None,
+    });
+
+    let mut seen = 0;
+    follower.run(None, |line| {
+        if line.output == Output::Marker {
+            return ControlFlow::Continue(());
         }
-    }
 
-    let _ = logs_process.kill();
-    let _ = logs_process.wait();
+        if use_color {
+            println!(
+                "[{} | {}] {}",
+                color_println_fmt(Color::Cyan, &get_timestamp()),
+                color_println_fmt(Color::Magenta, DSD),
+                line.text
+            );
+        } else {
+            println!("[{} | {}] {}", &get_timestamp(), DSD, line.text);
+        }
+
+        seen += 1;
+        if up_to_date.is_match(&line.text) && seen > 1 {
+            // first update check has happened after deployment
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_SOCK: &str = "/run/user/1000/podman/podman.sock";
+
+    fn resolve(container_host: Option<&str>, xdg: Option<&str>, existing: &[&str]) -> String {
+        resolve_podman_sock(container_host, xdg, |path| existing.contains(&path)).unwrap()
+    }
+
+    #[test]
+    fn podman_prefers_the_rootless_socket() {
+        assert_eq!(
+            resolve(None, Some("/run/user/1000"), &[USER_SOCK, PODMAN_SOCK]),
+            USER_SOCK
+        );
+        assert_eq!(
+            resolve(None, Some("/run/user/1000"), &[USER_SOCK]),
+            USER_SOCK
+        );
+    }
+
+    #[test]
+    fn podman_falls_back_to_the_rootful_socket() {
+        assert_eq!(
+            resolve(None, Some("/run/user/1000"), &[PODMAN_SOCK]),
+            PODMAN_SOCK
+        );
+        assert_eq!(resolve(None, None, &[PODMAN_SOCK]), PODMAN_SOCK);
+        assert_eq!(resolve(None, Some(""), &[]), PODMAN_SOCK);
+    }
+
+    #[test]
+    fn podman_uses_the_rootless_socket_when_neither_exists() {
+        // the user service may be socket-activated later, there is no rootful one to use
+        assert_eq!(resolve(None, Some("/run/user/1000"), &[]), USER_SOCK);
+    }
+
+    #[test]
+    fn podman_container_host_wins() {
+        assert_eq!(
+            resolve(
+                Some("unix:///tmp/podman.sock"),
+                Some("/run/user/1000"),
+                &[USER_SOCK]
+            ),
+            "/tmp/podman.sock"
+        );
+        assert_eq!(resolve(Some(""), None, &[PODMAN_SOCK]), PODMAN_SOCK);
+
+        let error = resolve_podman_sock(Some("tcp://host:8080"), None, |_| true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "dsd-util only supports unix:// CONTAINER_HOST. Got: tcp://host:8080"
+        );
+    }
+}
@@ -0,0 +1,518 @@
+//! Minimal blocking HTTP/1.1 client, enough to talk to the Docker Engine API
+
+use anyhow::Context;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// A parsed HTTP response whose body is read lazily
+pub struct Response<S: Read> {
+    pub status: u16,
+    pub body: Body<S>,
+}
+
+impl<S: Read> Response<S> {
+    /// Returns true for 2xx responses
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Reads the remaining body into a String
+    pub fn text(mut self) -> anyhow::Result<String> {
+        let mut body = String::new();
+        self.body
+            .read_to_string(&mut body)
+            .context("Failed to read response body")?;
+        Ok(body)
+    }
+}
+
+/// How the end of a response body is determined
+enum Framing {
+    Length(u64),
+    Chunked { remaining: u64, done: bool },
+    Eof,
+}
+
+/// How a response body is framed, for callers that read the stream themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    Length(u64),
+    Chunked,
+    Eof,
+}
+
+/// Response body reader that undoes content-length or chunked framing
+pub struct Body<S: Read> {
+    reader: BufReader<S>,
+    framing: Framing,
+}
+
+impl<S: Read> Body<S> {
+    /// Returns the underlying stream, e.g. to shut it down from another thread
+    pub fn get_ref(&self) -> &S {
+        self.reader.get_ref()
+    }
+
+    /// Splits an unread body into its stream, the bytes already buffered from the
+    /// stream and its framing, e.g. to read it without blocking with a [`BodyDecoder`]
+    pub fn into_raw(self) -> (S, Vec<u8>, BodyFraming) {
+        let framing = match self.framing {
+            Framing::Length(length) => BodyFraming::Length(length),
+            Framing::Chunked { .. } => BodyFraming::Chunked,
+            Framing::Eof => BodyFraming::Eof,
+        };
+        let buffered = self.reader.buffer().to_vec();
+
+        (self.reader.into_inner(), buffered, framing)
+    }
+
+    /// Reads the size line of the next chunk, returning 0 for the last chunk
+    fn next_chunk_size(&mut self) -> io::Result<u64> {
+        let mut line = String::new();
+        // skip the CRLF that terminates the previous chunk
+        while line.trim().is_empty() {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(0);
+            }
+        }
+
+        let size = line.trim().split(';').next().unwrap_or_default();
+        u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))
+    }
+}
+
+impl<S: Read> Read for Body<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.framing {
+            Framing::Eof => self.reader.read(buf),
+            Framing::Length(0) => Ok(0),
+            Framing::Length(remaining) => {
+                let max = buf.len().min(remaining as usize);
+                let n = self.reader.read(&mut buf[..max])?;
+                self.framing = Framing::Length(remaining - n as u64);
+                Ok(n)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { remaining: 0, .. } => {
+                let size = self.next_chunk_size()?;
+                self.framing = Framing::Chunked {
+                    remaining: size,
+                    done: size == 0,
+                };
+                self.read(buf)
+            }
+            Framing::Chunked { remaining, .. } => {
+                let max = buf.len().min(remaining as usize);
+                let n = self.reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    self.framing = Framing::Chunked {
+                        remaining: 0,
+                        done: true,
+                    };
+                    return Ok(0);
+                }
+                self.framing = Framing::Chunked {
+                    remaining: remaining - n as u64,
+                    done: false,
+                };
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// Where a [`BodyDecoder`] is within chunked framing
+enum ChunkState {
+    /// Reading a chunk size line, skipping the CRLF that ends the previous chunk
+    Size(Vec<u8>),
+    Data(u64),
+}
+
+/// Undoes body framing incrementally, for bytes read from a non-blocking stream
+pub struct BodyDecoder {
+    framing: BodyFraming,
+    chunk: ChunkState,
+    done: bool,
+}
+
+impl BodyDecoder {
+    pub fn new(framing: BodyFraming) -> Self {
+        BodyDecoder {
+            framing,
+            chunk: ChunkState::Size(vec![]),
+            done: framing == BodyFraming::Length(0),
+        }
+    }
+
+    /// Returns true once the end of the body was decoded
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Appends the body bytes contained in `input` to `out`
+    pub fn decode(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        match self.framing {
+            BodyFraming::Eof => {
+                out.extend_from_slice(input);
+                return Ok(());
+            }
+            BodyFraming::Length(remaining) => {
+                let n = input.len().min(remaining as usize);
+                out.extend_from_slice(&input[..n]);
+                self.framing = BodyFraming::Length(remaining - n as u64);
+                self.done = remaining == n as u64;
+                return Ok(());
+            }
+            BodyFraming::Chunked => {}
+        }
+
+        while !input.is_empty() && !self.done {
+            match &mut self.chunk {
+                ChunkState::Size(line) => {
+                    let Some(end) = input.iter().position(|&b| b == b'\n') else {
+                        line.extend_from_slice(input);
+                        return Ok(());
+                    };
+                    line.extend_from_slice(&input[..end]);
+                    input = &input[end + 1..];
+
+                    let text = String::from_utf8_lossy(line);
+                    let size = text.trim().split(';').next().unwrap_or_default();
+                    if size.is_empty() {
+                        line.clear();
+                        continue;
+                    }
+                    let size = u64::from_str_radix(size, 16).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size")
+                    })?;
+
+                    self.done = size == 0;
+                    self.chunk = ChunkState::Data(size);
+                }
+                ChunkState::Data(remaining) => {
+                    let n = input.len().min(*remaining as usize);
+                    out.extend_from_slice(&input[..n]);
+                    input = &input[n..];
+                    *remaining -= n as u64;
+                    if *remaining == 0 {
+                        self.chunk = ChunkState::Size(vec![]);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends a single request on `stream` and parses the response head
+///
+/// Requests are sent with `Connection: close`, so each stream carries exactly one request.
+pub fn send<S: Read + Write>(
+    mut stream: S,
+    method: &str,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+) -> anyhow::Result<Response<S>> {
+    let mut head = format!("{method} {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    stream
+        .write_all(head.as_bytes())
+        .context("Failed to send request")?;
+    if let Some(body) = body {
+        stream
+            .write_all(body)
+            .context("Failed to send request body")?;
+    }
+    stream.flush().context("Failed to send request")?;
+
+    let mut reader = BufReader::new(stream);
+
+    let mut status_line = String::new();
+    reader
+        .read_line(&mut status_line)
+        .context("Failed to read response")?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .with_context(|| format!("Malformed HTTP status line: {}", status_line.trim()))?;
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        if reader
+            .read_line(&mut line)
+            .context("Failed to read response headers")?
+            == 0
+        {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+
+    let framing = if method == "HEAD" || status == 204 || status == 304 {
+        Framing::Length(0)
+    } else if header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        Framing::Chunked {
+            remaining: 0,
+            done: false,
+        }
+    } else if let Some(length) = header("Content-Length").and_then(|v| v.parse().ok()) {
+        Framing::Length(length)
+    } else {
+        Framing::Eof
+    };
+
+    Ok(Response {
+        status,
+        body: Body { reader, framing },
+    })
+}
+
+/// Percent-encodes a query string component
+pub fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::JoinHandle;
+
+    /// Serves `response` to a single connection on a fresh socket in the temp dir,
+    /// returning the socket path and a handle yielding the raw request received
+    ///
+    /// The socket file is removed once the connection was accepted.
+    pub(crate) fn serve_once(response: &[u8]) -> (PathBuf, JoinHandle<String>) {
+        let (path, server) = serve(&[response]);
+
+        (
+            path,
+            std::thread::spawn(move || server.join().unwrap().remove(0)),
+        )
+    }
+
+    /// Serves each of `responses` to one connection in turn, returning the socket path
+    /// and a handle yielding the raw requests received
+    ///
+    /// The socket file is removed once the last connection was accepted.
+    pub(crate) fn serve(responses: &[&[u8]]) -> (PathBuf, JoinHandle<Vec<String>>) {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "dsd-util-test-{}-{}.sock",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let responses = responses.iter().map(|r| r.to_vec()).collect::<Vec<_>>();
+        let socket = path.clone();
+
+        let server = std::thread::spawn(move || {
+            let count = responses.len();
+            let mut requests = vec![];
+            for (i, response) in responses.into_iter().enumerate() {
+                let (stream, _) = listener.accept().unwrap();
+                if i + 1 == count {
+                    let _ = std::fs::remove_file(&socket);
+                }
+                let mut reader = BufReader::new(stream);
+
+                let mut request = String::new();
+                while reader.read_line(&mut request).unwrap() > 2 {}
+
+                let length = request
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .map_or(0, |l| l.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+
+                reader.get_mut().write_all(&response).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        (path, server)
+    }
+
+    fn get(response: &str) -> (Response<UnixStream>, String) {
+        let (path, server) = serve_once(response.as_bytes());
+        let stream = UnixStream::connect(&path).unwrap();
+
+        let response = send(stream, "GET", "docker", "/_ping", &[], None).unwrap();
+        let request = server.join().unwrap();
+
+        (response, request)
+    }
+
+    #[test]
+    fn send_writes_the_request_head_and_body() {
+        let (path, server) = serve_once(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let stream = UnixStream::connect(&path).unwrap();
+
+        let response = send(
+            stream,
+            "POST",
+            "docker",
+            "/containers/web/restart",
+            &[("Content-Type", "application/json")],
+            Some(b"{}"),
+        )
+        .unwrap();
+        let request = server.join().unwrap();
+
+        assert!(response.is_success());
+        assert_eq!(response.text().unwrap(), "");
+        assert_eq!(
+            request,
+            "POST /containers/web/restart HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: 2\r\n\r\n{}"
+        );
+    }
+
+    #[test]
+    fn send_reads_a_content_length_body() {
+        let (response, request) =
+            get("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nApi-Version: 1.45\r\n\r\nOKtrailing");
+
+        assert!(request.starts_with("GET /_ping HTTP/1.1\r\n"));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text().unwrap(), "OK");
+    }
+
+    #[test]
+    fn send_decodes_a_chunked_body() {
+        let (response, _) = get("HTTP/1.1 200 OK\r\ntransfer-encoding: Chunked\r\n\r\n\
+             5\r\nhello\r\n6;name=value\r\n world\r\n0\r\n\r\n");
+
+        assert_eq!(response.text().unwrap(), "hello world");
+    }
+
+    #[test]
+    fn send_reads_an_unframed_body_until_eof() {
+        let (response, _) =
+            get("HTTP/1.1 404 Not Found\r\n\r\n{\"message\":\"no such container\"}");
+
+        assert!(!response.is_success());
+        assert_eq!(response.status, 404);
+        assert_eq!(
+            response.text().unwrap(),
+            "{\"message\":\"no such container\"}"
+        );
+    }
+
+    #[test]
+    fn send_rejects_a_malformed_status_line() {
+        let (path, server) = serve_once(b"garbage\r\n\r\n");
+        let stream = UnixStream::connect(&path).unwrap();
+
+        let error = send(stream, "GET", "docker", "/_ping", &[], None)
+            .err()
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(error.to_string(), "Malformed HTTP status line: garbage");
+    }
+
+    #[test]
+    fn into_raw_hands_out_bytes_read_past_the_head() {
+        let (response, _) =
+            get("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n");
+
+        let (_, buffered, framing) = response.body.into_raw();
+        let mut decoder = BodyDecoder::new(framing);
+        let mut body = vec![];
+        decoder.decode(&buffered, &mut body).unwrap();
+
+        assert_eq!(framing, BodyFraming::Chunked);
+        assert_eq!(body, b"abc");
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn body_decoder_joins_chunk_headers_split_across_reads() {
+        let input = b"5\r\nhello\r\n10;ext\r\n0123456789abcdef\r\n0\r\n\r\n";
+
+        for split in [1, 2, 3, 7] {
+            let mut decoder = BodyDecoder::new(BodyFraming::Chunked);
+            let mut body = vec![];
+            for piece in input.chunks(split) {
+                decoder.decode(piece, &mut body).unwrap();
+            }
+
+            assert_eq!(body, b"hello0123456789abcdef", "split every {split} bytes");
+            assert!(decoder.is_done());
+        }
+    }
+
+    #[test]
+    fn body_decoder_stops_at_the_content_length() {
+        let mut decoder = BodyDecoder::new(BodyFraming::Length(5));
+        let mut body = vec![];
+
+        decoder.decode(b"hel", &mut body).unwrap();
+        assert!(!decoder.is_done());
+        decoder.decode(b"lo, extra", &mut body).unwrap();
+
+        assert_eq!(body, b"hello");
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn body_decoder_rejects_an_invalid_chunk_size() {
+        let mut decoder = BodyDecoder::new(BodyFraming::Chunked);
+
+        let error = decoder.decode(b"zz\r\n", &mut vec![]).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn encode_escapes_reserved_characters() {
+        assert_eq!(encode("media/web:1.0"), "media%2Fweb%3A1.0");
+        assert_eq!(encode("{\"a\":[1]}"), "%7B%22a%22%3A%5B1%5D%7D");
+    }
+}
@@ -1,4 +1,12 @@
 pub mod commands;
 pub mod deployer;
+mod http;
+pub mod logs;
+pub mod models;
+pub mod output;
 pub mod printer;
+pub mod runtime;
+pub mod selector;
+pub mod sink;
+pub mod stats;
 pub mod utils;
@@ -0,0 +1,1401 @@
+use crate::models::ContainerEvent;
+use crate::printer::{color_println_fmt, colorize_log_text, has_error_level, name_color, Color};
+use crate::runtime::{
+    ContainerRuntime, EventStopper, LogOptions, LogSource, LogStream, StreamKind,
+};
+use crate::selector::{glob_match, Selector, Target};
+use crate::sink::{BatchPolicy, LogSink, RotationPolicy, DEFAULT_SYSLOG_SOCKET};
+use crate::utils::{get_timestamp, parse_size, parse_std_duration, parse_time_arg};
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) mod poll;
+
+use poll::{catch_interrupts, interrupted, poll_readable};
+
+pub const DEFAULT_ARG_TAIL: &str = "100";
+const DEFAULT_MERGE_WINDOW: &str = "250ms";
+const DEFAULT_LEVEL_KEYS: &str = "level,lvl,severity,log.level";
+const DEFAULT_MESSAGE_KEYS: &str = "msg,message,log";
+const DEFAULT_TIME_KEYS: &str = "time,ts,timestamp,@timestamp";
+const DEFAULT_LOKI_BATCH_WAIT: &str = "1s";
+const DEFAULT_RATE_WINDOW: &str = "10s";
+
+/// How often container start events are checked for while no lines arrive
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes read from a ready stream at a time
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Longest line kept whole, longer output without a newline is split into lines
+const MAX_LINE_LENGTH: usize = 256 * 1024;
+
+/// Lines held back for merging at most, the earliest are emitted early beyond it
+const MAX_MERGE_PENDING: usize = 10_000;
+
+const RECREATED_MARKER: &str = "--- container recreated ---";
+const RESTARTED_MARKER: &str = "--- container restarted ---";
+const STARTED_MARKER: &str = "--- container started ---";
+
+/// Options for the `logs` command
+#[derive(Debug, Clone, clap::Args)]
+pub struct LogsArgs {
+    /// Set the number of lines to show from end of logs
+    #[arg(short, long, default_value = DEFAULT_ARG_TAIL)]
+    pub tail: u32,
+
+    /// Only show lines since a relative time (e.g. 2h, 1h30m) or an RFC3339 timestamp
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only show lines until a relative time (e.g. 10m) or an RFC3339 timestamp
+    #[arg(long)]
+    pub until: Option<String>,
+
+    /// Print the selected lines and exit instead of following
+    #[arg(long)]
+    pub no_follow: bool,
+
+    /// Show the full-precision timestamp docker recorded for each line
+    #[arg(long)]
+    pub timestamps: bool,
+
+    /// How lines written to stderr are marked
+    #[arg(long, value_enum, default_value_t = StderrStyle::Gutter)]
+    pub stderr_style: StderrStyle,
+
+    /// Order lines across containers by timestamp instead of arrival
+    #[arg(short, long)]
+    pub merge: bool,
+
+    /// How long live lines are held back to be ordered when merging (e.g. 250ms, 1s)
+    #[arg(long, default_value = DEFAULT_MERGE_WINDOW, value_parser = parse_std_duration)]
+    pub merge_window: Duration,
+
+    /// Only show lines matching this regex (can be repeated, any match passes)
+    #[arg(short, long, value_name = "REGEX")]
+    pub grep: Vec<Regex>,
+
+    /// Hide lines matching this regex (can be repeated)
+    #[arg(long, value_name = "REGEX")]
+    pub exclude_grep: Vec<Regex>,
+
+    /// Highlight matches of this regex without filtering (can be repeated)
+    #[arg(long, value_name = "REGEX")]
+    pub highlight: Vec<Regex>,
+
+    /// Wait for a line matching this regex and exit, lines from before now are only
+    /// searched when --since is given
+    #[arg(long, value_name = "REGEX")]
+    pub until_match: Option<Regex>,
+
+    /// Give up waiting for --until-match after this long (e.g. 30s, 5m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_std_duration, requires = "until_match")]
+    pub timeout: Option<Duration>,
+
+    /// Show N lines of the same container before and after each --grep match
+    #[arg(short = 'C', long, value_name = "N", default_value_t = 0)]
+    pub context: usize,
+
+    /// Render JSON lines compactly as `LEVEL message key=value ...`
+    #[arg(short, long)]
+    pub json: bool,
+
+    /// Keys holding the level of JSON lines, the first one present is used
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_LEVEL_KEYS)]
+    pub level_key: Vec<String>,
+
+    /// Keys holding the message of JSON lines, the first one present is used
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_MESSAGE_KEYS)]
+    pub message_key: Vec<String>,
+
+    /// Keys holding the time of JSON lines, shown in place of docker's timestamp
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_TIME_KEYS)]
+    pub time_key: Vec<String>,
+
+    /// Only show JSON lines where a field (`a.b` when nested) matches a value or glob
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_field_filter)]
+    pub field: Vec<(String, String)>,
+
+    /// Write each container's lines to DIR/<container>.log instead of the terminal
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Rotate a container's file once it reaches this size (e.g. 512K, 10M, 1G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "output_dir")]
+    pub rotate_size: Option<u64>,
+
+    /// Rotate a container's file once it has been written to for this long (e.g. 1h, 1d)
+    #[arg(long, value_name = "DURATION", value_parser = parse_std_duration, requires = "output_dir")]
+    pub rotate_every: Option<Duration>,
+
+    /// Gzip rotated files
+    #[arg(long, requires = "output_dir")]
+    pub compress: bool,
+
+    /// Number of rotated files kept per container
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub retain: usize,
+
+    /// Forward lines to syslog as RFC 5424, at a socket path or udp://host:port
+    #[arg(long, value_name = "TARGET", num_args = 0..=1, default_missing_value = DEFAULT_SYSLOG_SOCKET)]
+    pub syslog: Option<String>,
+
+    /// Push lines to a Loki-compatible endpoint, e.g. http://localhost:3100
+    #[arg(long, value_name = "URL")]
+    pub loki: Option<String>,
+
+    /// Push to Loki once this many lines are buffered
+    #[arg(long, value_name = "N", default_value_t = 500, requires = "loki")]
+    pub loki_batch_size: usize,
+
+    /// Push buffered lines to Loki at least this often (e.g. 500ms, 5s)
+    #[arg(long, value_name = "DURATION", default_value = DEFAULT_LOKI_BATCH_WAIT, value_parser = parse_std_duration, requires = "loki")]
+    pub loki_batch_wait: Duration,
+
+    /// Sample new lines for a while (10s by default) and report lines/s, bytes/s,
+    /// error lines and log file size per container and stack instead of printing lines
+    #[arg(
+        long,
+        value_name = "WINDOW",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_RATE_WINDOW,
+        value_parser = parse_std_duration,
+        conflicts_with_all = ["since", "until", "no_follow", "merge", "until_match", "output_dir", "syslog", "loki"]
+    )]
+    pub rate: Option<Duration>,
+}
+
+/// How lines a container wrote to stderr are told apart from stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StderrStyle {
+    /// Put a `!` in a gutter left of stderr lines, red when colored
+    #[default]
+    Gutter,
+    /// Print the text of stderr lines in red, without level colors or highlights
+    Red,
+    /// Print stderr lines like stdout lines
+    Plain,
+}
+
+/// Why `--until-match` gave up before a line matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaitError {
+    /// `--timeout` passed
+    TimedOut(Duration),
+    /// Every followed container stopped logging, e.g. because it exited
+    Ended,
+    /// Ctrl-C was pressed
+    Interrupted,
+}
+
+impl WaitError {
+    /// Exit code for the failure: 124 on timeout like `timeout(1)`, 130 when
+    /// interrupted and 1 when the logs ended
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WaitError::TimedOut(_) => 124,
+            WaitError::Ended => 1,
+            WaitError::Interrupted => 130,
+        }
+    }
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::TimedOut(timeout) => {
+                write!(f, "No matching line within {}s", timeout.as_secs_f64())
+            }
+            WaitError::Ended => write!(f, "Logs ended without a matching line"),
+            WaitError::Interrupted => write!(f, "Interrupted before a matching line"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+/// Parses a `key=value` field filter
+fn parse_field_filter(filter: &str) -> anyhow::Result<(String, String)> {
+    match filter.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => anyhow::bail!("Expected KEY=VALUE, got '{filter}'"),
+    }
+}
+
+impl LogsArgs {
+    /// Rotation of archived files from `--rotate-size`, `--rotate-every`, `--compress` and `--retain`
+    pub fn rotation_policy(&self) -> RotationPolicy {
+        RotationPolicy {
+            max_size: self.rotate_size,
+            max_age: self.rotate_every,
+            compress: self.compress,
+            retain: self.retain,
+        }
+    }
+
+    /// Batching of Loki pushes from `--loki-batch-size` and `--loki-batch-wait`
+    pub fn batch_policy(&self) -> BatchPolicy {
+        let max_lines = self.loki_batch_size.max(1);
+
+        BatchPolicy {
+            max_lines,
+            max_wait: self.loki_batch_wait,
+            // hold a few batches while the endpoint is down
+            max_pending: max_lines * 20,
+        }
+    }
+
+    /// Parses a line as a JSON object when JSON rendering or field filters are enabled
+    fn parse_json(&self, text: &str) -> Option<Map<String, Value>> {
+        let text = text.trim();
+        if (!self.json && self.field.is_empty()) || !text.starts_with('{') {
+            return None;
+        }
+
+        serde_json::from_str(text).ok()
+    }
+
+    /// Whether a parsed line satisfies every `--field` filter
+    fn fields_match(&self, json: Option<&Map<String, Value>>) -> bool {
+        self.field.iter().all(|(key, pattern)| {
+            json.and_then(|json| lookup_field(json, key))
+                .is_some_and(|value| glob_match(pattern, &value_text(value)))
+        })
+    }
+
+    /// Builds the runtime options, resolving relative times against now
+    pub fn log_options(&self) -> anyhow::Result<LogOptions> {
+        let mut since = self.since.as_deref().map(parse_time_arg).transpose()?;
+        // a line logged before waiting started must not satisfy --until-match
+        if since.is_none() && self.until_match.is_some() && !self.no_follow {
+            since = Some(Utc::now());
+        }
+
+        Ok(LogOptions {
+            tail: Some(self.tail),
+            since,
+            until: self.until.as_deref().map(parse_time_arg).transpose()?,
+            follow: !self.no_follow,
+            // always requested so lines carry the time they were emitted
+            timestamps: true,
+        })
+    }
+}
+
+/// Which output stream of a container a line was written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Output {
+    Stdout,
+    Stderr,
+    /// Inserted by dsd-util itself, e.g. when a container is attached again
+    Marker,
+}
+
+/// A single line of container output
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub container: Arc<str>,
+    /// Compose stack of the container, if it belongs to one
+    pub stack: Option<Arc<str>>,
+    pub output: Output,
+    /// When docker received the line, if it was reported
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub text: String,
+}
+
+impl LogLine {
+    /// Builds a line, splitting off the RFC3339 timestamp docker adds with `--timestamps`
+    pub fn parse(container: Arc<str>, output: Output, mut raw: String) -> Self {
+        let parsed = raw.split_once(' ').and_then(|(ts, _)| {
+            DateTime::parse_from_rfc3339(ts)
+                .ok()
+                .map(|timestamp| (timestamp, ts.len()))
+        });
+
+        // drop the timestamp in place rather than copying the text
+        let timestamp = parsed.map(|(timestamp, len)| {
+            raw.drain(..=len);
+            timestamp
+        });
+
+        LogLine {
+            container,
+            stack: None,
+            output,
+            timestamp,
+            text: raw,
+        }
+    }
+}
+
+impl LogLine {
+    /// Builds a marker line for a container, stamped with the current time
+    pub fn marker(container: &str, text: &str) -> Self {
+        LogLine {
+            container: Arc::from(container),
+            stack: None,
+            output: Output::Marker,
+            timestamp: Some(Local::now().fixed_offset()),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// Looks up a field by its exact key, or as a dotted path into nested objects
+fn lookup_field<'a>(json: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    json.get(key).or_else(|| {
+        let (first, rest) = key.split_once('.')?;
+        match json.get(first)? {
+            Value::Object(nested) => lookup_field(nested, rest),
+            _ => None,
+        }
+    })
+}
+
+/// Renders a JSON value without quotes around strings
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Removes the first of `keys` present in the object, returning it with its value
+fn take_first(json: &mut Map<String, Value>, keys: &[String]) -> Option<(String, Value)> {
+    keys.iter().find_map(|key| json.remove_entry(key))
+}
+
+/// Parses a JSON time given as RFC3339 or as unix seconds or milliseconds
+fn parse_json_time(value: &Value) -> Option<DateTime<FixedOffset>> {
+    match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s).ok(),
+        Value::Number(n) => {
+            let n = n.as_f64()?;
+            // anything past the year 5138 in seconds is taken as milliseconds
+            let millis = if n.abs() < 1e11 { n * 1000.0 } else { n };
+            DateTime::from_timestamp_millis(millis as i64).map(|t| t.fixed_offset())
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites a JSON line as `LEVEL message key=value ...`, taking its timestamp
+/// from the time field when it has one
+fn render_json_line(mut line: LogLine, mut json: Map<String, Value>, args: &LogsArgs) -> LogLine {
+    let level = take_first(&mut json, &args.level_key).map(|(_, l)| value_text(&l).to_uppercase());
+    let message = take_first(&mut json, &args.message_key).map(|(_, m)| value_text(&m));
+
+    if let Some((key, time)) = take_first(&mut json, &args.time_key) {
+        match parse_json_time(&time) {
+            Some(timestamp) => line.timestamp = Some(timestamp),
+            // keep times that could not be parsed visible with the other fields
+            None => {
+                json.insert(key, time);
+            }
+        }
+    }
+
+    let mut parts = vec![];
+    parts.extend(level.map(|l| format!("{l:<5}")));
+    parts.extend(message);
+    parts.extend(json.iter().map(|(key, value)| {
+        let text = value_text(value);
+        if text.contains(char::is_whitespace) {
+            format!("{key}={text:?}")
+        } else {
+            format!("{key}={text}")
+        }
+    }));
+
+    line.text = parts.join(" ");
+    line
+}
+
+/// Formats a line as `[timestamp | container] text`
+///
+/// The timestamp is shown in local time to the second, or exactly as docker recorded
+/// it when `full_timestamps` is set. Lines without one fall back to the receive time.
+pub fn format_log_line(line: &LogLine, use_color: bool, full_timestamps: bool) -> String {
+    format!(
+        "{} {}",
+        format_log_prefix(line, use_color, full_timestamps),
+        line.text
+    )
+}
+
+/// Formats the `[timestamp | container]` prefix of a line
+fn format_log_prefix(line: &LogLine, use_color: bool, full_timestamps: bool) -> String {
+    let timestamp = match line.timestamp {
+        Some(ts) if full_timestamps => ts.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        Some(ts) => ts
+            .with_timezone(&Local)
+            .format("%Y-%m-%dT%H:%M:%S")
+            .to_string(),
+        None => get_timestamp(),
+    };
+
+    if use_color {
+        format!(
+            "[{} | {}]",
+            color_println_fmt(Color::Cyan, &timestamp),
+            color_println_fmt(name_color(&line.container), &line.container),
+        )
+    } else {
+        format!("[{} | {}]", timestamp, line.container)
+    }
+}
+
+/// Per-container state for `--context` lines
+#[derive(Default)]
+struct ContextState {
+    /// Recent lines that did not match, shown if a match follows
+    before: VecDeque<LogLine>,
+    /// Number of lines still to show after the last match
+    after: usize,
+}
+
+/// A sink lines are handed to, with whether its last write failed
+struct SinkState {
+    sink: Box<dyn LogSink>,
+    failing: bool,
+}
+
+/// Filters, highlights and prints log lines according to [`LogsArgs`], handing the
+/// lines that pass to any sinks as well
+pub struct LogPrinter<'a> {
+    args: &'a LogsArgs,
+    use_color: bool,
+    to_terminal: bool,
+    sinks: Vec<SinkState>,
+    context: HashMap<Arc<str>, ContextState>,
+}
+
+impl<'a> LogPrinter<'a> {
+    pub fn new(args: &'a LogsArgs, use_color: bool) -> Self {
+        LogPrinter {
+            args,
+            use_color,
+            to_terminal: true,
+            sinks: vec![],
+            context: HashMap::new(),
+        }
+    }
+
+    /// Hands every printed line to `sink` as well
+    pub fn with_sink(mut self, sink: Box<dyn LogSink>) -> Self {
+        self.sinks.push(SinkState {
+            sink,
+            failing: false,
+        });
+        self
+    }
+
+    /// Sets whether lines are printed to stdout, or only handed to the sinks
+    pub fn to_terminal(mut self, to_terminal: bool) -> Self {
+        self.to_terminal = to_terminal;
+        self
+    }
+
+    /// Flushes the sinks, reporting failures
+    pub fn finish(&mut self) {
+        for state in &mut self.sinks {
+            if let Err(e) = state.sink.flush() {
+                report_sink_error(state.sink.as_ref(), &e, self.use_color);
+            }
+        }
+    }
+
+    /// Prints a line if it passes the filters, along with any context lines it brings in
+    pub fn print(&mut self, line: LogLine) {
+        // markers are never filtered out
+        if line.output == Output::Marker {
+            self.print_line(&line);
+            return;
+        }
+
+        let args = self.args;
+        let json = args.parse_json(&line.text);
+
+        // excluded lines are never shown, not even as context
+        if args.exclude_grep.iter().any(|re| re.is_match(&line.text))
+            || !args.fields_match(json.as_ref())
+        {
+            return;
+        }
+
+        let matched = args.grep.is_empty() || args.grep.iter().any(|re| re.is_match(&line.text));
+        let line = match json {
+            Some(json) if args.json => render_json_line(line, json, args),
+            _ => line,
+        };
+
+        let context = args.context;
+        if context == 0 || args.grep.is_empty() {
+            if matched {
+                self.print_line(&line);
+            }
+            return;
+        }
+
+        let state = self.context.entry(Arc::clone(&line.container)).or_default();
+
+        if matched {
+            let before = std::mem::take(&mut state.before);
+            state.after = context;
+            before.iter().for_each(|l| self.print_line(l));
+            self.print_line(&line);
+        } else if state.after > 0 {
+            state.after -= 1;
+            self.print_line(&line);
+        } else {
+            if state.before.len() == context {
+                state.before.pop_front();
+            }
+            state.before.push_back(line);
+        }
+    }
+
+    fn print_line(&mut self, line: &LogLine) {
+        for state in &mut self.sinks {
+            match state.sink.write(line) {
+                Ok(()) => state.failing = false,
+                // report once when a sink starts failing rather than for every line
+                Err(e) if !state.failing => {
+                    state.failing = true;
+                    report_sink_error(state.sink.as_ref(), &e, self.use_color);
+                }
+                Err(_) => {}
+            }
+        }
+
+        if !self.to_terminal {
+            return;
+        }
+
+        let mut prefix = format_log_prefix(line, self.use_color, self.args.timestamps);
+        let is_stderr = line.output == Output::Stderr;
+        if self.args.stderr_style == StderrStyle::Gutter {
+            let gutter = match (is_stderr, self.use_color) {
+                (true, true) => color_println_fmt(Color::Red, "!"),
+                (true, false) => "!".to_string(),
+                (false, _) => " ".to_string(),
+            };
+            prefix = format!("{gutter} {prefix}");
+        }
+
+        if self.use_color && line.output == Output::Marker {
+            println!("{prefix} {}", color_println_fmt(Color::Yellow, &line.text));
+        } else if self.use_color && is_stderr && self.args.stderr_style == StderrStyle::Red {
+            println!("{prefix} {}", color_println_fmt(Color::Red, &line.text));
+        } else if self.use_color {
+            let highlights = self
+                .args
+                .highlight
+                .iter()
+                .chain(&self.args.grep)
+                .collect::<Vec<_>>();
+            println!("{prefix} {}", colorize_log_text(&line.text, &highlights));
+        } else {
+            println!("{prefix} {}", line.text);
+        }
+    }
+}
+
+/// Prints a failure to write to a sink
+fn report_sink_error(sink: &dyn LogSink, error: &anyhow::Error, use_color: bool) {
+    let message = format!("[ERROR] - Failed to write to {}: {error:#}", sink.name());
+    if use_color {
+        println!("{}", color_println_fmt(Color::Red, &message));
+    } else {
+        println!("{message}");
+    }
+}
+
+/// A followed container with the sources of its log stream still open
+struct Attached {
+    container: Arc<str>,
+    stack: Option<Arc<str>>,
+    /// Stops the `docker logs` process or shuts down the socket once dropped
+    _stream: LogStream,
+    sources: Vec<LogSource>,
+    /// Sources are read once right after attaching, for output received while opening
+    fresh: bool,
+    /// Incomplete last line of stdout and stderr
+    partial: [Vec<u8>; 2],
+}
+
+impl Attached {
+    /// Reads the sources that are ready, appending complete lines to `lines`
+    ///
+    /// Sources that ended are removed, flushing their incomplete last line.
+    fn read(&mut self, ready: &[bool], buf: &mut [u8], lines: &mut Vec<LogLine>) {
+        let Attached {
+            container,
+            stack,
+            sources,
+            fresh,
+            partial,
+            ..
+        } = self;
+
+        let mut index = 0;
+        sources.retain_mut(|source| {
+            let is_ready = *fresh || ready.get(index).copied().unwrap_or(false);
+            index += 1;
+            if !is_ready {
+                return true;
+            }
+
+            let open = source
+                .read(buf, &mut |kind, bytes| {
+                    split_lines(container, stack, kind, bytes, partial, lines);
+                })
+                .unwrap_or(false);
+
+            if !open {
+                // an engine source carries both outputs
+                for (kind, partial) in [StreamKind::Stdout, StreamKind::Stderr]
+                    .into_iter()
+                    .zip(partial.iter_mut())
+                {
+                    if !partial.is_empty() {
+                        let bytes = std::mem::take(partial);
+                        lines.push(log_line(container, stack, kind, &bytes));
+                    }
+                }
+            }
+            open
+        });
+
+        *fresh = false;
+    }
+}
+
+/// Splits bytes of one output into lines, keeping an incomplete last line in `partial`
+///
+/// Lines longer than [`MAX_LINE_LENGTH`] are split, so output without newlines cannot
+/// grow the buffer without bound.
+fn split_lines(
+    container: &Arc<str>,
+    stack: &Option<Arc<str>>,
+    kind: StreamKind,
+    mut bytes: &[u8],
+    partial: &mut [Vec<u8>; 2],
+    lines: &mut Vec<LogLine>,
+) {
+    let partial = match kind {
+        StreamKind::Stdout => &mut partial[0],
+        StreamKind::Stderr => &mut partial[1],
+    };
+
+    while let Some(end) = bytes.iter().position(|&b| b == b'\n') {
+        let line = if partial.is_empty() {
+            log_line(container, stack, kind, &bytes[..end])
+        } else {
+            partial.extend_from_slice(&bytes[..end]);
+            let line = log_line(container, stack, kind, partial);
+            partial.clear();
+            line
+        };
+        lines.push(line);
+        bytes = &bytes[end + 1..];
+    }
+
+    partial.extend_from_slice(bytes);
+    if partial.len() >= MAX_LINE_LENGTH {
+        let bytes = std::mem::take(partial);
+        lines.push(log_line(container, stack, kind, &bytes));
+    }
+}
+
+/// Builds a line of a container from its bytes, without the line ending
+fn log_line(
+    container: &Arc<str>,
+    stack: &Option<Arc<str>>,
+    kind: StreamKind,
+    bytes: &[u8],
+) -> LogLine {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    let output = match kind {
+        StreamKind::Stdout => Output::Stdout,
+        StreamKind::Stderr => Output::Stderr,
+    };
+
+    LogLine {
+        stack: stack.clone(),
+        ..LogLine::parse(
+            Arc::clone(container),
+            output,
+            String::from_utf8_lossy(bytes).into_owned(),
+        )
+    }
+}
+
+/// Reads every line of the given containers' logs without following, ordered by timestamp
+///
+/// Lines without a timestamp keep their position relative to the lines of the same stream.
+pub fn collect_sorted(
+    runtime: &dyn ContainerRuntime,
+    targets: &[Target],
+    options: &LogOptions,
+    use_color: bool,
+) -> Vec<LogLine> {
+    let options = LogOptions {
+        follow: false,
+        ..options.clone()
+    };
+
+    let mut follower = LogFollower::new(runtime, options, use_color);
+    for target in targets {
+        follower.attach(target);
+    }
+
+    let mut lines = vec![];
+    follower.run(None, |line| {
+        lines.push(line);
+        ControlFlow::Continue(())
+    });
+
+    // untimestamped lines, such as the continuations of split long lines, sort
+    // with the last timestamped line of their stream
+    let mut last = HashMap::new();
+    let mut keyed = lines
+        .into_iter()
+        .map(|line| {
+            let stream = (Arc::clone(&line.container), line.output);
+            let timestamp = match line.timestamp {
+                Some(timestamp) => {
+                    last.insert(stream, timestamp);
+                    Some(timestamp)
+                }
+                None => last.get(&stream).copied(),
+            };
+            (timestamp, line)
+        })
+        .collect::<Vec<_>>();
+
+    keyed.sort_by_key(|(timestamp, _)| *timestamp);
+    keyed.into_iter().map(|(_, line)| line).collect()
+}
+
+/// Log volume of a container over a sampling window
+#[derive(Debug, Clone, Default)]
+pub struct LogRate {
+    pub container: String,
+    pub stack: Option<String>,
+    pub lines: u64,
+    /// Bytes of text logged, counting the newline but not docker's timestamp
+    pub bytes: u64,
+    /// Lines containing an error level token such as ERROR or FATAL
+    pub errors: u64,
+}
+
+/// Counts the lines the given containers log from now until `window` passed or Ctrl-C
+/// is pressed, returning the counts and how long was actually sampled
+pub fn sample_rates(
+    runtime: &dyn ContainerRuntime,
+    targets: &[Target],
+    window: Duration,
+    use_color: bool,
+) -> (Vec<LogRate>, Duration) {
+    let options = LogOptions {
+        tail: None,
+        since: Some(Utc::now()),
+        until: None,
+        follow: true,
+        timestamps: true,
+    };
+
+    let mut rates = targets
+        .iter()
+        .map(|t| LogRate {
+            container: t.name.clone(),
+            stack: t.stack.clone(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let index = targets
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.name.clone(), i))
+        .collect::<HashMap<_, _>>();
+
+    let started = Instant::now();
+    let mut follower = LogFollower::new(runtime, options, use_color).deadline(started + window);
+    for target in targets {
+        follower.attach(target);
+    }
+
+    follower.run(None, |line| {
+        if line.output != Output::Marker
+            && let Some(&i) = index.get(&*line.container)
+        {
+            let rate = &mut rates[i];
+            rate.lines += 1;
+            rate.bytes += line.text.len() as u64 + 1;
+            if has_error_level(&line.text) {
+                rate.errors += 1;
+            }
+        }
+        ControlFlow::Continue(())
+    });
+
+    (rates, started.elapsed())
+}
+
+/// Follows the logs of containers into a single stream of lines
+///
+/// Every followed stream is read from one thread by polling its file descriptors, so
+/// following many containers does not need a thread per stream. Reads are bounded and
+/// lines are handed on before more is read, so a container logging faster than lines
+/// are printed is held back by its pipe or socket filling up.
+///
+/// When watching, containers that start while following are attached too: containers
+/// already followed (restarted or recreated under the same name) and new containers
+/// matching the selection. A marker line is emitted for each of them.
+pub struct LogFollower<'a> {
+    runtime: &'a dyn ContainerRuntime,
+    options: LogOptions,
+    use_color: bool,
+    streams: Vec<Attached>,
+    /// Id of the container followed under each name
+    followed: HashMap<String, String>,
+    events: Option<Receiver<ContainerEvent>>,
+    /// Stops the events process once following ends
+    events_stopper: Option<EventStopper>,
+    selector: Option<&'a Selector>,
+    deadline: Option<Instant>,
+}
+
+impl Drop for LogFollower<'_> {
+    fn drop(&mut self) {
+        // the events thread would otherwise block until the next event arrives
+        if let Some(stopper) = &self.events_stopper {
+            stopper.stop();
+        }
+    }
+}
+
+/// Why [`LogFollower::run`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowEnd {
+    /// The logs of every followed container ended
+    Ended,
+    /// `emit` asked to stop
+    Stopped,
+    /// The deadline passed
+    TimedOut,
+    /// Ctrl-C was pressed or the process was asked to terminate
+    Interrupted,
+}
+
+impl<'a> LogFollower<'a> {
+    pub fn new(runtime: &'a dyn ContainerRuntime, options: LogOptions, use_color: bool) -> Self {
+        LogFollower {
+            runtime,
+            options,
+            use_color,
+            streams: vec![],
+            followed: HashMap::new(),
+            events: None,
+            events_stopper: None,
+            selector: None,
+            deadline: None,
+        }
+    }
+
+    /// Starts following a container
+    pub fn attach(&mut self, target: &Target) {
+        let options = self.options.clone();
+        self.attach_with(target, &options);
+    }
+
+    /// Opens the logs of a container, printing the failure when they can't be opened
+    fn attach_with(&mut self, target: &Target, options: &LogOptions) {
+        let opened = self
+            .runtime
+            .logs(&target.name, options)
+            .and_then(|mut stream| {
+                let sources = stream.take_sources()?;
+                Ok((stream, sources))
+            });
+
+        let Ok((stream, sources)) = opened else {
+            let message = format!("[ERROR] - Failed to log {}", target.name);
+            if self.use_color {
+                println!("{}", color_println_fmt(Color::Red, &message));
+            } else {
+                println!("{message}");
+            }
+            return;
+        };
+
+        self.followed.insert(target.name.clone(), target.id.clone());
+        self.streams.push(Attached {
+            container: Arc::from(target.name.as_str()),
+            stack: target.stack.as_deref().map(Arc::from),
+            _stream: stream,
+            sources,
+            fresh: true,
+            partial: Default::default(),
+        });
+    }
+
+    /// Watches for containers starting that are followed already or match `selector`
+    pub fn watch(&mut self, selector: &'a Selector) -> anyhow::Result<()> {
+        let events = self.runtime.events(&["start"])?;
+        self.events_stopper = Some(events.stopper());
+        let (tx, rx) = std::sync::mpsc::channel::<ContainerEvent>();
+
+        std::thread::spawn(move || {
+            for event in events {
+                if tx.send(event).is_err() {
+                    break; // Follower finished
+                }
+            }
+        });
+
+        self.events = Some(rx);
+        self.selector = Some(selector);
+
+        Ok(())
+    }
+
+    /// Attaches to containers reported as started since the last call
+    fn attach_started(&mut self, emit: &mut impl FnMut(LogLine)) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        let mut started = vec![];
+        let disconnected = loop {
+            match events.try_recv() {
+                Ok(event) => started.push(event),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+
+        if disconnected {
+            // no more events, finish with the last followed container
+            self.events = None;
+        }
+
+        for event in started {
+            let container = event.container();
+            let marker = match self.followed.get(&container.name) {
+                Some(id) if *id == container.id => RESTARTED_MARKER,
+                Some(_) => RECREATED_MARKER,
+                None if self.selector.is_some_and(|s| s.matches(&container)) => STARTED_MARKER,
+                None => continue,
+            };
+
+            let target = Target::from(container);
+            emit(LogLine {
+                stack: target.stack.as_deref().map(Arc::from),
+                ..LogLine::marker(&target.name, marker)
+            });
+
+            // only what the new container logged, its backlog was not asked for
+            let options = LogOptions {
+                tail: None,
+                since: Some(event.time().unwrap_or_else(Utc::now)),
+                ..self.options.clone()
+            };
+            self.attach_with(&target, &options);
+        }
+    }
+
+    /// Stops following once `deadline` passes
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Reads lines until the logs of every followed container end, calling `emit`
+    /// in arrival order, or in timestamp order within `merge_window` when set
+    ///
+    /// While watching, this keeps waiting for containers to start again. Following
+    /// also ends when `emit` breaks, the deadline passes or on Ctrl-C, stopping every
+    /// stream once the lines already read are emitted.
+    pub fn run(
+        mut self,
+        merge_window: Option<Duration>,
+        mut emit: impl FnMut(LogLine) -> ControlFlow<()>,
+    ) -> FollowEnd {
+        catch_interrupts();
+
+        let stopped = Cell::new(false);
+        let mut emit = |line: LogLine| {
+            if !stopped.get() {
+                stopped.set(emit(line).is_break());
+            }
+        };
+
+        let mut merge = merge_window.map(MergeBuffer::new);
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        let mut lines = vec![];
+
+        let end = loop {
+            if stopped.get() {
+                // lines after the one that stopped following are not wanted
+                return FollowEnd::Stopped;
+            }
+            if interrupted() {
+                break FollowEnd::Interrupted;
+            }
+            if self.deadline.is_some_and(|d| d <= Instant::now()) {
+                break FollowEnd::TimedOut;
+            }
+            if self.streams.is_empty() && self.events.is_none() {
+                break FollowEnd::Ended;
+            }
+
+            let mut timeout = if self.streams.iter().any(|a| a.fresh) {
+                Duration::ZERO
+            } else {
+                merge.as_ref().map_or(EVENT_POLL_INTERVAL, |m| {
+                    m.next_deadline().min(EVENT_POLL_INTERVAL)
+                })
+            };
+            if let Some(deadline) = self.deadline {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+
+            let fds = self
+                .streams
+                .iter()
+                .flat_map(|a| a.sources.iter().map(|s| s as &dyn AsFd))
+                .collect::<Vec<_>>();
+            let ready = match poll_readable(&fds, timeout) {
+                Ok(ready) => ready,
+                Err(e) => {
+                    let message = format!("[ERROR] - Failed to wait for logs: {e}");
+                    if self.use_color {
+                        println!("{}", color_println_fmt(Color::Red, &message));
+                    } else {
+                        println!("{message}");
+                    }
+                    break FollowEnd::Ended;
+                }
+            };
+
+            let mut ready = ready.as_slice();
+            for attached in &mut self.streams {
+                let (own, rest) = ready.split_at(attached.sources.len().min(ready.len()));
+                ready = rest;
+                attached.read(own, &mut buf, &mut lines);
+            }
+            self.streams.retain(|a| !a.sources.is_empty());
+
+            for line in lines.drain(..) {
+                match merge.as_mut() {
+                    Some(merge) => merge.push(line),
+                    None => emit(line),
+                }
+            }
+
+            if let Some(merge) = merge.as_mut() {
+                merge.pop_ready().into_iter().for_each(&mut emit);
+            }
+
+            self.attach_started(&mut emit);
+        };
+
+        if let Some(merge) = merge.as_mut() {
+            merge.drain().into_iter().for_each(&mut emit);
+        }
+
+        if stopped.get() {
+            FollowEnd::Stopped
+        } else {
+            end
+        }
+    }
+}
+
+/// Line held back by a [`MergeBuffer`], ordered by timestamp then arrival
+struct Pending {
+    key: (DateTime<Utc>, u64),
+    ready_at: Instant,
+    line: LogLine,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Holds live lines for a short window so lines arriving out of order from
+/// different containers can be emitted by timestamp
+pub struct MergeBuffer {
+    window: Duration,
+    pending: BinaryHeap<Reverse<Pending>>,
+    seq: u64,
+}
+
+impl MergeBuffer {
+    pub fn new(window: Duration) -> Self {
+        MergeBuffer {
+            window,
+            pending: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Buffers a line; lines without a timestamp are ordered by arrival time
+    pub fn push(&mut self, line: LogLine) {
+        let timestamp = line
+            .timestamp
+            .map_or_else(Utc::now, |ts| ts.with_timezone(&Utc));
+        self.seq += 1;
+        self.pending.push(Reverse(Pending {
+            key: (timestamp, self.seq),
+            ready_at: Instant::now() + self.window,
+            line,
+        }));
+    }
+
+    /// Pops the earliest lines whose window has passed, or that overflow the buffer
+    pub fn pop_ready(&mut self) -> Vec<LogLine> {
+        let now = Instant::now();
+        let mut ready = vec![];
+        while self.pending.len() > MAX_MERGE_PENDING
+            || self
+                .pending
+                .peek()
+                .is_some_and(|Reverse(p)| p.ready_at <= now)
+        {
+            if let Some(Reverse(pending)) = self.pending.pop() {
+                ready.push(pending.line);
+            }
+        }
+        ready
+    }
+
+    /// Empties the buffer in timestamp order
+    pub fn drain(&mut self) -> Vec<LogLine> {
+        let mut lines = vec![];
+        while let Some(Reverse(pending)) = self.pending.pop() {
+            lines.push(pending.line);
+        }
+        lines
+    }
+
+    /// Time until the earliest buffered line is ready, or the full window if empty
+    fn next_deadline(&self) -> Duration {
+        self.pending.peek().map_or(self.window, |Reverse(p)| {
+            p.ready_at.saturating_duration_since(Instant::now())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{FakeCall, FakeContainer, FakeRuntime};
+
+    fn target(name: &str) -> Target {
+        Target {
+            id: name.to_string(),
+            name: name.to_string(),
+            stack: None,
+        }
+    }
+
+    fn texts(lines: &[LogLine]) -> Vec<&str> {
+        lines.iter().map(|l| l.text.as_str()).collect()
+    }
+
+    fn follow<'a>(runtime: &'a FakeRuntime, names: &[&str]) -> LogFollower<'a> {
+        let options = LogOptions {
+            follow: true,
+            ..Default::default()
+        };
+        let mut follower = LogFollower::new(runtime, options, false);
+        for name in names {
+            follower.attach(&target(name));
+        }
+        follower
+    }
+
+    #[test]
+    fn follower_reads_both_outputs_until_the_logs_end() {
+        let mut web = FakeContainer::new("aaa", "web").log_lines(&["listening"]);
+        web.stderr = vec!["warning: slow".to_string()];
+        let runtime = FakeRuntime::new(vec![web]);
+
+        let mut lines = vec![];
+        let end = follow(&runtime, &["web"]).run(None, |line| {
+            lines.push(line);
+            ControlFlow::Continue(())
+        });
+
+        assert_eq!(end, FollowEnd::Ended);
+        lines.sort_by_key(|l| l.output == Output::Stderr);
+        assert_eq!(texts(&lines), ["listening", "warning: slow"]);
+        assert_eq!(lines[0].output, Output::Stdout);
+        assert_eq!(lines[1].output, Output::Stderr);
+        assert_eq!(&*lines[0].container, "web");
+        assert_eq!(runtime.calls(), [FakeCall::Logs("web".to_string())]);
+    }
+
+    #[test]
+    fn follower_stops_once_emit_breaks() {
+        let web = FakeContainer::new("aaa", "web").log_lines(&["starting", "ready", "serving"]);
+        let runtime = FakeRuntime::new(vec![web]);
+        let ready = Regex::new("^ready$").unwrap();
+
+        let mut lines = vec![];
+        let end = follow(&runtime, &["web"]).run(None, |line| {
+            let matched = ready.is_match(&line.text);
+            lines.push(line);
+            if matched {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+
+        assert_eq!(end, FollowEnd::Stopped);
+        assert_eq!(texts(&lines), ["starting", "ready"]);
+    }
+
+    #[test]
+    fn follower_attaches_containers_started_while_watching() {
+        let old_web = FakeContainer::new("aaa", "web")
+            .stack("media")
+            .log_lines(&["old"]);
+        let new_web = FakeContainer::new("ccc", "web")
+            .stack("media")
+            .log_lines(&["new"]);
+        let db = FakeContainer::new("bbb", "db")
+            .stack("media")
+            .log_lines(&["db up"]);
+        let proxy = FakeContainer::new("ddd", "proxy")
+            .stack("edge")
+            .log_lines(&["proxy up"]);
+        let runtime = FakeRuntime::new(vec![old_web]).with_started(vec![db, new_web, proxy]);
+        let selector = Selector {
+            stacks: vec!["media".to_string()],
+            ..Default::default()
+        };
+
+        let mut follower = follow(&runtime, &["web"]);
+        follower.watch(&selector).unwrap();
+        let mut lines = vec![];
+        let end = follower.run(None, |line| {
+            lines.push(line);
+            ControlFlow::Continue(())
+        });
+
+        assert_eq!(end, FollowEnd::Ended);
+        let markers = lines
+            .iter()
+            .filter(|l| l.output == Output::Marker)
+            .map(|l| (&*l.container, l.text.as_str(), l.stack.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            [
+                ("db", STARTED_MARKER, Some("media")),
+                ("web", RECREATED_MARKER, Some("media"))
+            ]
+        );
+        let mut logged = lines
+            .iter()
+            .filter(|l| l.output != Output::Marker)
+            .map(|l| (&*l.container, l.text.as_str()))
+            .collect::<Vec<_>>();
+        logged.sort();
+        assert_eq!(logged, [("db", "db up"), ("web", "new"), ("web", "old")]);
+        assert_eq!(
+            runtime.calls(),
+            [
+                FakeCall::Logs("web".to_string()),
+                FakeCall::Logs("db".to_string()),
+                FakeCall::Logs("web".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn follower_skips_containers_whose_logs_fail_to_open() {
+        let runtime = FakeRuntime::new(vec![FakeContainer::new("aaa", "web").log_lines(&["up"])]);
+
+        let mut lines = vec![];
+        let end = follow(&runtime, &["gone", "web"]).run(None, |line| {
+            lines.push(line);
+            ControlFlow::Continue(())
+        });
+
+        assert_eq!(end, FollowEnd::Ended);
+        assert_eq!(texts(&lines), ["up"]);
+    }
+
+    #[test]
+    fn collect_sorted_orders_lines_across_containers() {
+        let runtime = FakeRuntime::new(vec![
+            FakeContainer::new("aaa", "web")
+                .log_lines(&["2024-01-01T00:00:02Z b", "2024-01-01T00:00:04Z d"]),
+            FakeContainer::new("bbb", "db")
+                .log_lines(&["2024-01-01T00:00:01Z a", "2024-01-01T00:00:03Z c"]),
+        ]);
+
+        let lines = collect_sorted(
+            &runtime,
+            &[target("web"), target("db")],
+            &LogOptions::default(),
+            false,
+        );
+
+        assert_eq!(texts(&lines), ["a", "b", "c", "d"]);
+        assert!(lines.iter().all(|l| l.timestamp.is_some()));
+    }
+
+    #[test]
+    fn collect_sorted_keeps_untimestamped_lines_after_their_stream() {
+        let runtime = FakeRuntime::new(vec![
+            FakeContainer::new("aaa", "web").log_lines(&["2024-01-01T00:00:03Z long", "continued"]),
+            FakeContainer::new("bbb", "db")
+                .log_lines(&["2024-01-01T00:00:01Z first", "2024-01-01T00:00:05Z last"]),
+        ]);
+
+        let lines = collect_sorted(
+            &runtime,
+            &[target("web"), target("db")],
+            &LogOptions::default(),
+            false,
+        );
+
+        assert_eq!(texts(&lines), ["first", "long", "continued", "last"]);
+        assert_eq!(lines[2].timestamp, None);
+    }
+}
@@ -0,0 +1,73 @@
+//! Readiness polling and Ctrl-C handling for the log loop and other long-running commands
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+
+/// Set once SIGINT or SIGTERM was received
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // a second Ctrl-C terminates right away
+    // SAFETY: signal() is async-signal-safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// Stops the process from being killed by SIGINT and SIGTERM, recording them for
+/// [`interrupted`] instead so followed streams can be shut down cleanly
+pub fn catch_interrupts() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            // SAFETY: the handler only touches an atomic and calls signal()
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_interrupt as *const () as libc::sighandler_t;
+                // no SA_RESTART, so a blocked poll returns right away
+                action.sa_flags = 0;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+    });
+}
+
+/// Returns true once SIGINT or SIGTERM was received after [`catch_interrupts`]
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Waits until any of `fds` can be read or has closed, or `timeout` passes
+///
+/// Returns which descriptors are ready, in the order given. Being interrupted by a
+/// signal counts as a timeout.
+pub fn poll_readable(fds: &[&dyn AsFd], timeout: Duration) -> io::Result<Vec<bool>> {
+    let mut pollfds = fds
+        .iter()
+        .map(|fd| libc::pollfd {
+            fd: fd.as_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    // SAFETY: pollfds is a valid, exclusively borrowed array of its length
+    let ready = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+
+    if ready < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Interrupted {
+            return Ok(vec![false; fds.len()]);
+        }
+        return Err(error);
+    }
+
+    Ok(pollfds.iter().map(|p| p.revents != 0).collect())
+}
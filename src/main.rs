@@ -1,11 +1,26 @@
 use clap::{Parser, Subcommand};
 use dsd_util::commands::{init, logs, nuke, restart, stats, update};
-
-const DEFAULT_ARG_TAIL: &str = "100";
+use dsd_util::logs::{LogsArgs, WaitError};
+use dsd_util::output::{self, OutputFormat, PartialFailure};
+use dsd_util::runtime::{self, RuntimeKind, Transport};
+use dsd_util::selector::Selector;
+use dsd_util::stats::StatsArgs;
 
 #[derive(Debug, Parser)]
 #[command(version, about = "A simple helper for managing your docker-stack-deploy containers.", long_about = None)]
 struct Cli {
+    /// Container runtime to manage
+    #[arg(long, global = true, value_enum, default_value_t = RuntimeKind::Auto)]
+    runtime: RuntimeKind,
+
+    /// How to talk to the runtime: the Engine API socket, the CLI, or auto-detect
+    #[arg(long, global = true, value_enum, default_value_t = Transport::Auto)]
+    transport: Transport,
+
+    /// Print results of stats, update and restart as text or machine-readable records
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -15,7 +30,7 @@ enum Commands {
     /// Initialize and bootstrap a new instance of docker-stack-deploy
     Init {
         /// Path where docker-stack-deploy compose file will be located
-        /// defaults to an XDG user path on rootless docker, /var/lib/docker-stack-deploy otherwise
+        /// defaults to an XDG user path on rootless docker or podman, /var/lib/docker-stack-deploy otherwise
         #[arg(short, long)]
         project_dir: Option<String>,
 
@@ -23,106 +38,94 @@ enum Commands {
         git_url: String,
     },
 
-    // TODO: Add more arg options for logs - since, filter, follow ?
     /// View container logs
     Logs {
-        /// View logs for specified containers
-        containers: Option<Vec<String>>,
-
-        /// View logs for specified stacks
-        #[arg(short, long)]
-        stacks: Option<Vec<String>>,
+        #[command(flatten)]
+        selector: Selector,
 
-        /// Set the number of lines to show from end of logs
-        #[arg(short, long, default_value = DEFAULT_ARG_TAIL)]
-        tail: u32,
-
-        /// View logs for all containers
-        #[arg(short, long)]
-        all: bool,
+        #[command(flatten)]
+        args: Box<LogsArgs>,
     },
 
     /// Kill all docker containers and redeploy docker-stack-deploy
     Nuke {
         /// Path where docker-stack-deploy compose file is located
-        /// defaults to an XDG user path on rootless docker, /var/lib/docker-stack-deploy otherwise
+        /// defaults to an XDG user path on rootless docker or podman, /var/lib/docker-stack-deploy otherwise
         #[arg(short, long)]
         project_dir: Option<String>,
     },
 
     /// Restart containers
     Restart {
-        /// Restart specified container
-        containers: Option<Vec<String>>,
-
-        /// Restart specified stacks
-        #[arg(short, long)]
-        stacks: Option<Vec<String>>,
-
-        /// Restart all containers
-        #[arg(short, long)]
-        all: bool,
+        #[command(flatten)]
+        selector: Selector,
     },
 
     /// View basic stats for docker containers
     Stats {
-        /// View stats for specified containers
-        containers: Option<Vec<String>>,
+        #[command(flatten)]
+        selector: Selector,
 
-        /// View stats for specified stacks
-        #[arg(short, long)]
-        stacks: Option<Vec<String>>,
-
-        /// View stats for all containers
-        #[arg(short, long)]
-        all: bool,
+        #[command(flatten)]
+        args: StatsArgs,
     },
 
     /// Update container images
     Update {
-        /// Update specified containers
-        containers: Option<Vec<String>>,
-
-        /// Update specified stacks
-        #[arg(short, long)]
-        stacks: Option<Vec<String>>,
-
-        /// Update all containers
-        #[arg(short, long)]
-        all: bool,
+        #[command(flatten)]
+        selector: Selector,
     },
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let format = cli.output;
+
+    if let Err(e) = run(cli) {
+        // scripts waiting with --until-match tell failures apart by exit code
+        if let Some(wait) = e.downcast_ref::<WaitError>() {
+            eprintln!("Error: {wait}");
+            std::process::exit(wait.exit_code());
+        }
+        if !format.is_structured() {
+            return Err(e);
+        }
+
+        // failures of single containers are already part of the emitted results
+        if e.downcast_ref::<PartialFailure>().is_some() {
+            eprintln!("Error: {e}");
+        } else {
+            output::emit_error(&mut std::io::stdout(), format, &e)?;
+        }
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let kind = cli.runtime.detect();
+    let runtime = runtime::connect(kind, cli.transport)?;
+    let runtime = runtime.as_ref();
 
     match cli.command {
         Commands::Init {
             project_dir,
             git_url,
-        } => init(project_dir, git_url)?,
-        Commands::Logs {
-            containers,
-            stacks,
-            tail,
-            all,
-        } => logs(containers, stacks, tail, all)?,
-        Commands::Nuke { project_dir } => nuke(project_dir)?,
-        Commands::Restart {
-            containers,
-            stacks,
-            all,
-        } => restart(containers, stacks, all)?,
-        Commands::Stats {
-            containers,
-            stacks,
-            all,
-        } => stats(containers, stacks, all)?,
-        Commands::Update {
-            containers,
-            stacks,
-            all,
-        } => update(containers, stacks, all)?,
+        } => init(runtime, kind, project_dir, git_url)?,
+        Commands::Logs { selector, args } => logs(runtime, &selector, &args)?,
+        Commands::Nuke { project_dir } => {
+            nuke(runtime, kind, project_dir, &mut std::io::stdin().lock())?
+        }
+        Commands::Restart { selector } => restart(runtime, &selector, cli.output)?,
+        Commands::Stats { selector, args } => stats(
+            runtime,
+            &selector,
+            &args,
+            cli.output,
+            &mut std::io::stdout(),
+        )?,
+        Commands::Update { selector } => update(runtime, &selector, cli.output)?,
     }
 
     Ok(())
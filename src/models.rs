@@ -0,0 +1,505 @@
+//! Serde models for the JSON the docker CLI and Engine API return
+//!
+//! `docker inspect` and `GET /containers/{id}/json` share the same shape, so both
+//! transports deserialize into [`ContainerInspect`].
+
+use crate::utils::{format_elapsed, InspectData, StatsData};
+use chrono::{DateTime, Datelike, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Label compose puts on every container with the project (stack) name
+pub const STACK_LABEL: &str = "com.docker.compose.project";
+
+/// Label compose puts on every container with the service name
+pub const SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// A container as returned when listing containers
+#[derive(Debug, Clone, Default)]
+pub struct ContainerSummary {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub state: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ContainerSummary {
+    /// Compose project (stack) the container belongs to
+    pub fn stack(&self) -> Option<&str> {
+        self.labels.get(STACK_LABEL).map(String::as_str)
+    }
+
+    /// Compose service the container was created for
+    pub fn service(&self) -> Option<&str> {
+        self.labels.get(SERVICE_LABEL).map(String::as_str)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+}
+
+impl From<ContainerInspect> for ContainerSummary {
+    fn from(inspect: ContainerInspect) -> Self {
+        ContainerSummary {
+            id: inspect.id,
+            name: inspect.name.trim_start_matches('/').to_string(),
+            image: inspect.config.image,
+            state: inspect.state.status,
+            labels: inspect.config.labels.unwrap_or_default(),
+        }
+    }
+}
+
+/// Subset of `docker inspect` output for a single container
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerInspect {
+    pub id: String,
+    pub name: String,
+    pub restart_count: u64,
+    pub state: ContainerState,
+    pub config: ContainerConfig,
+    pub host_config: HostConfig,
+    pub network_settings: NetworkSettings,
+    /// json-file log of the container, empty for other log drivers
+    pub log_path: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerState {
+    pub status: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub exit_code: i64,
+    #[serde(rename = "OOMKilled")]
+    pub oom_killed: bool,
+    pub health: Option<Health>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct Health {
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerConfig {
+    pub image: String,
+    pub tty: bool,
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct HostConfig {
+    pub restart_policy: Option<RestartPolicy>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct RestartPolicy {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct NetworkSettings {
+    pub ports: Option<BTreeMap<String, Option<Vec<PortBinding>>>>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct PortBinding {
+    pub host_port: String,
+}
+
+impl ContainerInspect {
+    /// Renders published ports as `80/tcp:8080 443/tcp`, or N/A when nothing is exposed
+    fn ports(&self) -> String {
+        match &self.network_settings.ports {
+            Some(ports) if !ports.is_empty() => ports
+                .iter()
+                .map(|(port, bindings)| match bindings.as_deref() {
+                    Some([binding, ..]) => format!("{port}:{}", binding.host_port),
+                    _ => port.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => "N/A".to_string(),
+        }
+    }
+}
+
+/// Parses a start or finish time of a container, None for the zero time docker
+/// reports when it never happened
+fn parse_state_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+        .filter(|t| t.year() > 1)
+}
+
+/// How long the container has been in its state: the uptime while it is up, or
+/// e.g. `exited 3H 5m ago (code 137)` once it stopped
+fn state_age(state: &ContainerState) -> String {
+    match state.status.as_str() {
+        "running" | "paused" | "restarting" => parse_state_time(&state.started_at)
+            .map_or_else(|| "N/A".to_string(), |started| format_elapsed(&started)),
+        status => match parse_state_time(&state.finished_at) {
+            Some(finished) => format!(
+                "{status} {} ago (code {})",
+                format_elapsed(&finished),
+                state.exit_code
+            ),
+            // created but never started, or a finish time that can't be read
+            None => status.to_string(),
+        },
+    }
+}
+
+impl From<ContainerInspect> for InspectData {
+    fn from(inspect: ContainerInspect) -> Self {
+        let ports = inspect.ports();
+        let uptime = state_age(&inspect.state);
+
+        let restart_policy = inspect
+            .host_config
+            .restart_policy
+            .map(|p| p.name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "no".to_string());
+
+        InspectData {
+            id: inspect.id,
+            container_name: inspect.name.trim_start_matches('/').to_string(),
+            restart_policy,
+            health: inspect
+                .state
+                .health
+                .map(|h| h.status)
+                .unwrap_or_else(|| "N/A".to_string()),
+            uptime,
+            started_at: parse_state_time(&inspect.state.started_at),
+            finished_at: parse_state_time(&inspect.state.finished_at),
+            restart_count: inspect.restart_count,
+            exit_code: inspect.state.exit_code,
+            oom_killed: inspect.state.oom_killed,
+            stack: inspect
+                .config
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(STACK_LABEL).cloned()),
+            status: inspect.state.status,
+            image: inspect.config.image,
+            ports,
+            log_path: inspect.log_path,
+        }
+    }
+}
+
+/// One line of `docker stats --no-stream --format '{{json .}}'`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CliStats {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "CPUPerc")]
+    pub cpu_perc: String,
+    #[serde(rename = "MemPerc")]
+    pub mem_perc: String,
+    /// Usage and limit, e.g. `12.5MiB / 1.944GiB`
+    #[serde(rename = "MemUsage")]
+    pub mem_usage: String,
+    /// Received and sent, e.g. `1.2kB / 648B`
+    #[serde(rename = "NetIO")]
+    pub net_io: String,
+    /// Read and written, e.g. `8.19kB / 0B`
+    #[serde(rename = "BlockIO")]
+    pub block_io: String,
+    #[serde(rename = "PIDs")]
+    pub pids: String,
+}
+
+/// Parses a percentage like `12.34%`, returning None for placeholders such as `--`
+pub fn parse_percent(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .ok()
+        .filter(|percent: &f64| percent.is_finite())
+}
+
+/// Parses a size as docker prints it, like `12.5MiB`, `3.2kB` or `0B`
+pub fn parse_byte_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let amount: f64 = value[..digits].parse().ok()?;
+
+    let multiplier = match value[digits..].trim() {
+        "B" | "" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+
+    Some((amount * multiplier).round() as u64)
+}
+
+/// Parses a pair of sizes like `12.5MiB / 1.944GiB`
+fn parse_size_pair(value: &str) -> Option<(u64, u64)> {
+    let (first, second) = value.split_once('/')?;
+    Some((parse_byte_size(first)?, parse_byte_size(second)?))
+}
+
+impl From<CliStats> for StatsData {
+    fn from(stats: CliStats) -> Self {
+        StatsData {
+            container_name: stats.name.trim_start_matches('/').to_string(),
+            cpu: parse_percent(&stats.cpu_perc),
+            memory: parse_percent(&stats.mem_perc),
+            memory_bytes: parse_size_pair(&stats.mem_usage).map(|(usage, _)| usage),
+            memory_limit: parse_size_pair(&stats.mem_usage).map(|(_, limit)| limit),
+            net_io: parse_size_pair(&stats.net_io),
+            block_io: parse_size_pair(&stats.block_io),
+            pids: stats.pids.trim().parse().ok(),
+        }
+    }
+}
+
+/// A container event from `docker events --format '{{json .}}'` or `GET /events`
+///
+/// Podman's CLI reports the id, name and attributes at the top level rather than
+/// under `Actor`, so both layouts are accepted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ContainerEvent {
+    #[serde(rename = "Action", alias = "Status")]
+    pub action: String,
+    #[serde(rename = "Actor")]
+    pub actor: EventActor,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Attributes")]
+    pub attributes: BTreeMap<String, String>,
+    /// Unix time in nanoseconds, only reported by docker
+    #[serde(rename = "timeNano")]
+    pub time_nano: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EventActor {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Attributes")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl ContainerEvent {
+    /// Id of the container the event is about
+    pub fn container_id(&self) -> &str {
+        if self.actor.id.is_empty() {
+            &self.id
+        } else {
+            &self.actor.id
+        }
+    }
+
+    /// When the event happened, if the runtime reported it precisely
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        (self.time_nano > 0).then(|| DateTime::from_timestamp_nanos(self.time_nano))
+    }
+
+    /// The container a start event is about, with the labels reported in its attributes
+    pub fn container(&self) -> ContainerSummary {
+        let mut labels = if self.actor.attributes.is_empty() {
+            self.attributes.clone()
+        } else {
+            self.actor.attributes.clone()
+        };
+
+        let name = labels.remove("name").unwrap_or_else(|| self.name.clone());
+        let image = labels.remove("image").unwrap_or_default();
+
+        ContainerSummary {
+            id: self.container_id().to_string(),
+            name,
+            image,
+            state: "running".to_string(),
+            labels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, SecondsFormat};
+
+    const ZERO_TIME: &str = "0001-01-01T00:00:00Z";
+
+    fn ago(duration: Duration) -> String {
+        (Utc::now() - duration).to_rfc3339_opts(SecondsFormat::Nanos, true)
+    }
+
+    fn state(status: &str, started_at: &str, finished_at: &str, exit_code: i64) -> ContainerState {
+        ContainerState {
+            status: status.to_string(),
+            started_at: started_at.to_string(),
+            finished_at: finished_at.to_string(),
+            exit_code,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn state_age_is_the_uptime_while_up() {
+        let started = ago(Duration::hours(26) + Duration::minutes(5));
+
+        for status in ["running", "paused", "restarting"] {
+            assert_eq!(
+                state_age(&state(status, &started, ZERO_TIME, 0)),
+                "1D 2H 5m"
+            );
+        }
+        assert_eq!(state_age(&state("running", ZERO_TIME, ZERO_TIME, 0)), "N/A");
+        assert_eq!(state_age(&state("running", "garbage", ZERO_TIME, 0)), "N/A");
+    }
+
+    #[test]
+    fn state_age_tells_how_long_ago_a_container_exited() {
+        let started = ago(Duration::days(3));
+        let finished = ago(Duration::hours(3) + Duration::minutes(5));
+
+        assert_eq!(
+            state_age(&state("exited", &started, &finished, 137)),
+            "exited 3H 5m ago (code 137)"
+        );
+        assert_eq!(
+            state_age(&state("dead", &started, &ago(Duration::seconds(30)), 1)),
+            "dead 0m ago (code 1)"
+        );
+    }
+
+    #[test]
+    fn state_age_is_the_status_without_a_finish_time() {
+        // created but never started
+        assert_eq!(
+            state_age(&state("created", ZERO_TIME, ZERO_TIME, 0)),
+            "created"
+        );
+        assert_eq!(
+            state_age(&state("exited", &ago(Duration::days(1)), "", 0)),
+            "exited"
+        );
+        assert_eq!(
+            state_age(&state("exited", &ago(Duration::days(1)), "yesterday", 0)),
+            "exited"
+        );
+    }
+
+    #[test]
+    fn parse_byte_size_reads_decimal_and_binary_units() {
+        assert_eq!(parse_byte_size("0B"), Some(0));
+        assert_eq!(parse_byte_size("648B"), Some(648));
+        assert_eq!(parse_byte_size("42"), Some(42));
+        assert_eq!(parse_byte_size("3.2kB"), Some(3200));
+        assert_eq!(parse_byte_size("3.2KB"), Some(3200));
+        assert_eq!(parse_byte_size("2KiB"), Some(2048));
+        assert_eq!(parse_byte_size("12.5MB"), Some(12_500_000));
+        assert_eq!(parse_byte_size("1.5GiB"), Some(1_610_612_736));
+        assert_eq!(parse_byte_size(" 2GiB "), Some(2_147_483_648));
+        assert_eq!(parse_byte_size("1TB"), Some(1_000_000_000_000));
+        assert_eq!(parse_byte_size("1 MiB"), Some(1_048_576));
+    }
+
+    #[test]
+    fn parse_byte_size_rejects_malformed_input() {
+        for value in [
+            "", "--", "B", "kB", "1.2.3kB", "-1B", "12XB", "1e3B", "12 mib",
+        ] {
+            assert_eq!(parse_byte_size(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn parse_percent_rejects_placeholders() {
+        assert_eq!(parse_percent("12.34%"), Some(12.34));
+        assert_eq!(parse_percent(" 0.00% "), Some(0.0));
+        for value in ["--", "", "%", "NaN%", "inf%"] {
+            assert_eq!(parse_percent(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn cli_stats_convert_into_stats_data() {
+        let stats: CliStats = serde_json::from_str(
+            r#"{"Name":"/web","CPUPerc":"1.25%","MemPerc":"75.00%","MemUsage":"1.5GiB / 2GiB","NetIO":"1.2kB / 648B","BlockIO":"8.19kB / 0B","PIDs":"12"}"#,
+        )
+        .unwrap();
+
+        let data = StatsData::from(stats);
+
+        assert_eq!(data.container_name, "web");
+        assert_eq!(data.cpu, Some(1.25));
+        assert_eq!(data.memory, Some(75.0));
+        assert_eq!(data.memory_bytes, Some(1_610_612_736));
+        assert_eq!(data.memory_limit, Some(2_147_483_648));
+        assert_eq!(data.net_io, Some((1200, 648)));
+        assert_eq!(data.block_io, Some((8190, 0)));
+        assert_eq!(data.pids, Some(12));
+    }
+
+    #[test]
+    fn cli_stats_of_stopped_containers_have_no_values() {
+        let stats = CliStats {
+            name: "old".to_string(),
+            cpu_perc: "--".to_string(),
+            mem_perc: "--".to_string(),
+            mem_usage: "-- / --".to_string(),
+            net_io: "--".to_string(),
+            block_io: "0B / --".to_string(),
+            pids: "--".to_string(),
+        };
+
+        let data = StatsData::from(stats);
+
+        assert_eq!(data.container_name, "old");
+        assert_eq!(data.cpu, None);
+        assert_eq!(data.memory, None);
+        assert_eq!(data.memory_bytes, None);
+        assert_eq!(data.memory_limit, None);
+        assert_eq!(data.net_io, None);
+        assert_eq!(data.block_io, None);
+        assert_eq!(data.pids, None);
+    }
+
+    #[test]
+    fn parse_state_time_treats_the_zero_time_as_none() {
+        assert_eq!(parse_state_time(ZERO_TIME), None);
+        assert_eq!(parse_state_time(""), None);
+        assert_eq!(
+            parse_state_time("2024-05-01T10:00:00.123456789+02:00"),
+            Some(
+                DateTime::parse_from_rfc3339("2024-05-01T08:00:00.123456789Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+    }
+}
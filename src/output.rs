@@ -0,0 +1,228 @@
+//! Machine-readable output of command results
+//!
+//! Commands collect their results as serializable records, which are either shown
+//! as text for people or printed as a list of records in the chosen format.
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::io::Write;
+
+/// How command results are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Tables and messages for people
+    #[default]
+    Text,
+    /// A JSON array of records
+    Json,
+    /// A YAML list of records
+    Yaml,
+    /// One CSV row per record, after a header row
+    Csv,
+}
+
+impl OutputFormat {
+    /// Whether results are printed as records rather than text
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+/// A failed command, emitted in place of its results
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub error: String,
+    /// What led to the error, outermost first
+    pub causes: Vec<String>,
+}
+
+impl From<&anyhow::Error> for ErrorRecord {
+    fn from(error: &anyhow::Error) -> Self {
+        ErrorRecord {
+            error: error.to_string(),
+            causes: error.chain().skip(1).map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+/// Error for a command whose failures were already reported in its results, so
+/// only the exit status needs to reflect them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialFailure {
+    pub action: &'static str,
+    pub failed: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to {} {} container(s)", self.action, self.failed)
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+/// Writes an error to `out` as a single structured object
+pub fn emit_error(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    error: &anyhow::Error,
+) -> anyhow::Result<()> {
+    let record = ErrorRecord::from(error);
+    let rendered = match format {
+        OutputFormat::Text => format!("Error: {error:#}\n"),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(&record)?),
+        OutputFormat::Yaml => serde_yml::to_string(&record)?,
+        OutputFormat::Csv => render_csv(&[record])?,
+    };
+    out.write_all(rendered.as_bytes())
+        .context("Failed to write output")
+}
+
+/// Renders records in a structured format, or nothing for [`OutputFormat::Text`]
+pub fn render<T: Serialize>(format: OutputFormat, records: &[T]) -> anyhow::Result<String> {
+    let rendered = match format {
+        OutputFormat::Text => String::new(),
+        OutputFormat::Json => {
+            let mut json = serde_json::to_string_pretty(records)?;
+            json.push('\n');
+            json
+        }
+        OutputFormat::Yaml => serde_yml::to_string(records)?,
+        OutputFormat::Csv => render_csv(records)?,
+    };
+
+    Ok(rendered)
+}
+
+/// Writes records to `out` in a structured format
+pub fn emit<T: Serialize>(
+    out: &mut dyn Write,
+    format: OutputFormat,
+    records: &[T],
+) -> anyhow::Result<()> {
+    out.write_all(render(format, records)?.as_bytes())
+        .context("Failed to write output")
+}
+
+/// Renders records as CSV, with the fields of the first record as the header
+///
+/// Missing values are left empty and nested values are written as JSON.
+fn render_csv<T: Serialize>(records: &[T]) -> anyhow::Result<String> {
+    let records = records
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to serialize records")?;
+
+    let Some(Value::Object(first)) = records.first() else {
+        return Ok(String::new());
+    };
+    let fields = first.keys().cloned().collect::<Vec<_>>();
+
+    let mut csv = fields
+        .iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(",");
+    csv.push('\n');
+
+    for record in &records {
+        let row = fields
+            .iter()
+            .map(|field| match record.get(field) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(text)) => csv_field(text),
+                Some(value) => csv_field(&value.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+
+    Ok(csv)
+}
+
+/// Quotes a field when it contains a separator, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn render_csv_quotes_separators_quotes_and_line_breaks() {
+        let records = [
+            json!({"name": "web", "note": "a,b", "ports": null}),
+            json!({"name": "say \"hi\"", "note": "two\nlines", "ports": [80, 443]}),
+            json!({"name": "cr\r", "note": "plain"}),
+        ];
+
+        assert_eq!(
+            render_csv(&records).unwrap(),
+            "name,note,ports\n\
+             web,\"a,b\",\n\
+             \"say \"\"hi\"\"\",\"two\nlines\",\"[80,443]\"\n\
+             \"cr\r\",plain,\n"
+        );
+    }
+
+    #[test]
+    fn render_csv_is_empty_without_records() {
+        assert_eq!(render_csv::<Value>(&[]).unwrap(), "");
+        assert_eq!(render(OutputFormat::Text, &[json!({"a": 1})]).unwrap(), "");
+    }
+
+    #[test]
+    fn emit_error_writes_the_error_and_its_causes() {
+        let error = anyhow::anyhow!("No such container: db").context("Failed to restart db");
+
+        let mut json = vec![];
+        emit_error(&mut json, OutputFormat::Json, &error).unwrap();
+        let record: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            record,
+            json!({"error": "Failed to restart db", "causes": ["No such container: db"]})
+        );
+
+        let mut csv = vec![];
+        emit_error(&mut csv, OutputFormat::Csv, &error).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "error,causes\nFailed to restart db,\"[\"\"No such container: db\"\"]\"\n"
+        );
+
+        let mut text = vec![];
+        emit_error(&mut text, OutputFormat::Text, &error).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "Error: Failed to restart db: No such container: db\n"
+        );
+    }
+
+    #[test]
+    fn partial_failure_names_the_action_and_count() {
+        let error = anyhow::Error::from(PartialFailure {
+            action: "restart",
+            failed: 2,
+        });
+
+        assert_eq!(error.to_string(), "Failed to restart 2 container(s)");
+        assert_eq!(
+            error.downcast_ref::<PartialFailure>(),
+            Some(&PartialFailure {
+                action: "restart",
+                failed: 2
+            })
+        );
+    }
+}
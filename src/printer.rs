@@ -1,7 +1,39 @@
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+
 const ANSI_RESET: &str = "\x1b[0m"; // ANSI reset code
+const ANSI_HIGHLIGHT: &str = "\x1b[1;7m"; // ANSI bold + reverse video
+const ANSI_ALT_SCREEN_ENTER: &str = "\x1b[?1049h\x1b[?25l"; // alternate screen, hide cursor
+const ANSI_ALT_SCREEN_LEAVE: &str = "\x1b[?25h\x1b[?1049l"; // show cursor, main screen
+const ANSI_CLEAR: &str = "\x1b[H\x1b[2J"; // cursor home, clear screen
+
+/// Common log level tokens, colored automatically in log output
+static LEVEL_TOKENS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(FATAL|PANIC|CRIT(?:ICAL)?|ERROR|ERR|WARN(?:ING)?|INFO|DEBUG|TRACE)\b")
+        .expect("level token regex is valid")
+});
+
+/// Colors container names are picked from, as RGB on the xterm 256-color cube so
+/// terminals without truecolor show exactly the same colors
+const NAME_PALETTE: [(u8, u8, u8); 12] = [
+    (0x5f, 0xaf, 0xff),
+    (0x5f, 0xd7, 0x87),
+    (0xff, 0xaf, 0x5f),
+    (0xd7, 0x87, 0xff),
+    (0x5f, 0xd7, 0xd7),
+    (0xff, 0x87, 0xaf),
+    (0xaf, 0xd7, 0x5f),
+    (0xff, 0xd7, 0x5f),
+    (0x87, 0xaf, 0xd7),
+    (0xd7, 0xaf, 0x87),
+    (0x87, 0xd7, 0xaf),
+    (0xaf, 0x87, 0xd7),
+];
 
 /// Color options for printing to the terminal
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
     Red,
     Green,
@@ -10,22 +42,65 @@ pub enum Color {
     Magenta,
     Cyan,
     White,
+    /// Index into the xterm 256-color palette
+    Ansi256(u8),
+    /// 24-bit color, for terminals that support truecolor
+    Rgb(u8, u8, u8),
 }
 
 /// Implement Color to match on proper ANSI code
 impl Color {
     /// Get ANSI code for color
-    fn code(&self) -> &str {
+    fn code(&self) -> Cow<'static, str> {
         match self {
-            Color::Red => "\x1b[1;31m",
-            Color::Green => "\x1b[1;32m",
-            Color::Blue => "\x1b[1;34m",
-            Color::Yellow => "\x1b[1;33m",
-            Color::Magenta => "\x1b[1;35m",
-            Color::Cyan => "\x1b[1;36m",
-            Color::White => "\x1b[1;37m",
+            Color::Red => "\x1b[1;31m".into(),
+            Color::Green => "\x1b[1;32m".into(),
+            Color::Blue => "\x1b[1;34m".into(),
+            Color::Yellow => "\x1b[1;33m".into(),
+            Color::Magenta => "\x1b[1;35m".into(),
+            Color::Cyan => "\x1b[1;36m".into(),
+            Color::White => "\x1b[1;37m".into(),
+            Color::Ansi256(index) => format!("\x1b[1;38;5;{index}m").into(),
+            Color::Rgb(r, g, b) => format!("\x1b[1;38;2;{r};{g};{b}m").into(),
         }
     }
+
+    /// Closest color of the xterm 256-color cube to an RGB color
+    pub fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> Color {
+        // the cube's six levels per channel are 0, 95, 135, 175, 215 and 255
+        let level = |c: u8| match c {
+            0..=47 => 0,
+            48..=114 => 1,
+            _ => (c - 35) / 40,
+        };
+
+        Color::Ansi256(16 + 36 * level(r) + 6 * level(g) + level(b))
+    }
+}
+
+/// Whether the terminal advertises 24-bit color support, checked once
+static TRUECOLOR: LazyLock<bool> =
+    LazyLock::new(|| std::env::var("COLORTERM").is_ok_and(|v| v == "truecolor" || v == "24bit"));
+
+/// Determine if the terminal advertises 24-bit color support
+pub fn supports_truecolor() -> bool {
+    *TRUECOLOR
+}
+
+/// Picks a color for a name from a fixed palette by hashing it, so a container keeps
+/// its color across runs
+pub fn name_color(name: &str) -> Color {
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    let (r, g, b) = NAME_PALETTE[(hash % NAME_PALETTE.len() as u64) as usize];
+
+    if supports_truecolor() {
+        Color::Rgb(r, g, b)
+    } else {
+        Color::rgb_to_ansi256(r, g, b)
+    }
 }
 
 /// Print line function that uses ANSI code to display colored text on terminal
@@ -37,3 +112,474 @@ pub fn color_println(color: Color, text: &str) {
 pub fn color_println_fmt(color: Color, text: &str) -> String {
     format!("{}{}{}", color.code(), text, ANSI_RESET)
 }
+
+/// Format string function that uses ANSI code to return string highlighted with reverse video
+pub fn highlight_fmt(text: &str) -> String {
+    format!("{ANSI_HIGHLIGHT}{text}{ANSI_RESET}")
+}
+
+/// Keeps the terminal on its alternate screen until dropped, so redrawn output does
+/// not scroll the shell away and the previous contents come back afterwards
+pub struct AlternateScreen;
+
+impl AlternateScreen {
+    /// Switches to the alternate screen and hides the cursor
+    pub fn enter() -> Self {
+        print!("{ANSI_ALT_SCREEN_ENTER}");
+        let _ = io::stdout().flush();
+        AlternateScreen
+    }
+
+    /// Replaces everything on the screen with `text`
+    pub fn redraw(&self, text: &str) {
+        print!("{ANSI_CLEAR}{text}");
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        print!("{ANSI_ALT_SCREEN_LEAVE}");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Columns are not shrunk below this width when fitting a table to the terminal
+const MIN_COLUMN_WIDTH: usize = 4;
+
+const ELLIPSIS: char = '…';
+
+/// Columns a character takes up in a terminal: 0 for control and combining
+/// characters, 2 for wide East Asian characters and emoji, 1 otherwise
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0..0x20 | 0x7f..0xa0 => 0,
+        0x300..=0x36f | 0x200b..=0x200f | 0x20d0..=0x20ff | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// Splits text into ANSI escape sequences and visible characters
+fn segments(text: &str) -> impl Iterator<Item = (&str, bool)> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        let len = if c != '\x1b' {
+            c.len_utf8()
+        } else if rest[1..].starts_with('[') {
+            // CSI: parameters up to a final byte in @..~
+            rest[2..]
+                .find(|c: char| ('@'..='~').contains(&c))
+                .map_or(rest.len(), |i| i + 3)
+        } else {
+            1 + rest[1..].chars().next().map_or(0, char::len_utf8)
+        };
+        let (segment, tail) = rest.split_at(len);
+        rest = tail;
+        Some((segment, c == '\x1b'))
+    })
+}
+
+/// Width of text in terminal columns, ignoring ANSI escape sequences
+pub fn visible_width(text: &str) -> usize {
+    segments(text)
+        .filter(|&(_, escape)| !escape)
+        .flat_map(|(segment, _)| segment.chars())
+        .map(char_width)
+        .sum()
+}
+
+/// Cuts text down to `width` columns, ending it with an ellipsis when anything was cut
+///
+/// Escape sequences are kept, and colors are reset after the ellipsis.
+pub fn truncate(text: &str, width: usize) -> String {
+    if visible_width(text) <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::with_capacity(text.len());
+    let mut used = 0;
+    let mut styled = false;
+    for (segment, escape) in segments(text) {
+        if escape {
+            styled = true;
+            truncated.push_str(segment);
+            continue;
+        }
+        let w = segment.chars().map(char_width).sum::<usize>();
+        if used + w + 1 > width {
+            break;
+        }
+        truncated.push_str(segment);
+        used += w;
+    }
+
+    if width > 0 {
+        truncated.push(ELLIPSIS);
+    }
+    if styled {
+        truncated.push_str(ANSI_RESET);
+    }
+    truncated
+}
+
+/// Width of the terminal stdout is attached to, None when it is not a terminal
+pub fn terminal_width() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only writes the window size into the struct passed
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+
+    (ok && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+enum TableLine {
+    Row(Vec<String>),
+    /// Spans the whole table, e.g. a group title
+    Text(String),
+}
+
+/// A table of cells that may contain ANSI colors, aligned by their visible width
+///
+/// Columns are as wide as their widest cell. When the table is wider than its
+/// maximum width the widest columns are shrunk and their cells cut with an ellipsis.
+pub struct Table {
+    headers: Vec<String>,
+    lines: Vec<TableLine>,
+    border: bool,
+    max_width: Option<usize>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<String>) -> Self {
+        Table {
+            headers,
+            lines: vec![],
+            border: false,
+            max_width: None,
+        }
+    }
+
+    /// Draws box-drawing borders around the table and between columns
+    pub fn border(mut self, border: bool) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Fits the table into `width` columns, e.g. from [`terminal_width`]
+    pub fn max_width(mut self, width: Option<usize>) -> Self {
+        self.max_width = width;
+        self
+    }
+
+    /// Adds a row, missing cells are left empty
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.lines.push(TableLine::Row(cells));
+    }
+
+    /// Adds a line of text spanning every column
+    pub fn text(&mut self, text: String) {
+        self.lines.push(TableLine::Text(text));
+    }
+
+    /// Widths of the columns, shrunk to fit the maximum width where possible
+    fn column_widths(&self) -> Vec<usize> {
+        let mut widths = self
+            .headers
+            .iter()
+            .map(|h| visible_width(h))
+            .collect::<Vec<_>>();
+        for line in &self.lines {
+            if let TableLine::Row(cells) = line {
+                for (width, cell) in widths.iter_mut().zip(cells) {
+                    *width = (*width).max(visible_width(cell));
+                }
+            }
+        }
+
+        if let Some(max_width) = self.max_width {
+            let mut total = self.total_width(&widths);
+            while total > max_width {
+                let Some((widest, &width)) = widths.iter().enumerate().max_by_key(|&(_, w)| *w)
+                else {
+                    break;
+                };
+                if width <= MIN_COLUMN_WIDTH {
+                    break;
+                }
+                widths[widest] -= 1;
+                total -= 1;
+            }
+        }
+
+        widths
+    }
+
+    /// Width of the whole table with the given column widths
+    fn total_width(&self, widths: &[usize]) -> usize {
+        let separators = widths.len().saturating_sub(1) * if self.border { 3 } else { 2 };
+        let edges = if self.border { 4 } else { 0 };
+        widths.iter().sum::<usize>() + separators + edges
+    }
+
+    fn render_cells(&self, cells: &[String], widths: &[usize]) -> String {
+        let cells = widths
+            .iter()
+            .enumerate()
+            .map(|(i, &width)| {
+                let cell = truncate(cells.get(i).map_or("", String::as_str), width);
+                let padding = width.saturating_sub(visible_width(&cell));
+                format!("{cell}{}", " ".repeat(padding))
+            })
+            .collect::<Vec<_>>();
+
+        if self.border {
+            format!("│ {} │", cells.join(" │ "))
+        } else {
+            cells.join("  ").trim_end().to_string()
+        }
+    }
+
+    fn render_text(&self, text: &str, widths: &[usize]) -> String {
+        if !self.border {
+            return match self.max_width {
+                Some(max_width) => truncate(text, max_width),
+                None => text.to_string(),
+            };
+        }
+
+        let inner = self.total_width(widths) - 4;
+        let text = truncate(text, inner);
+        let padding = inner.saturating_sub(visible_width(&text));
+        format!("│ {text}{} │", " ".repeat(padding))
+    }
+
+    /// Border line such as `├──┼──┤`
+    fn rule(widths: &[usize], left: char, middle: char, right: char) -> String {
+        let segments = widths
+            .iter()
+            .map(|w| "─".repeat(w + 2))
+            .collect::<Vec<_>>()
+            .join(&middle.to_string());
+        format!("{left}{segments}{right}")
+    }
+
+    /// Renders the table, one line per row ending in a newline
+    pub fn render(&self) -> String {
+        let widths = self.column_widths();
+        let mut output = String::new();
+
+        if self.border {
+            output.push_str(&Self::rule(&widths, '┌', '┬', '┐'));
+            output.push('\n');
+        }
+        output.push_str(&self.render_cells(&self.headers, &widths));
+        output.push('\n');
+        if self.border {
+            output.push_str(&Self::rule(&widths, '├', '┼', '┤'));
+            output.push('\n');
+        }
+
+        for line in &self.lines {
+            let line = match line {
+                TableLine::Row(cells) => self.render_cells(cells, &widths),
+                TableLine::Text(text) => self.render_text(text, &widths),
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+
+        if self.border {
+            output.push_str(&Self::rule(&widths, '└', '┴', '┘'));
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+/// Color for a log level token
+fn level_color(token: &str) -> Color {
+    match token {
+        "INFO" => Color::Green,
+        "DEBUG" | "TRACE" => Color::Blue,
+        "WARN" | "WARNING" => Color::Yellow,
+        _ => Color::Red,
+    }
+}
+
+/// Whether text contains an error level token (ERROR, FATAL, ...) in any case
+pub fn has_error_level(text: &str) -> bool {
+    LEVEL_TOKENS
+        .find_iter(&text.to_ascii_uppercase())
+        .any(|m| level_color(m.as_str()) == Color::Red)
+}
+
+/// Colors log level tokens (ERROR, WARN, INFO, ...) and highlights matches of `highlights`
+///
+/// Highlighted matches take precedence over level colors where they overlap.
+pub fn colorize_log_text(text: &str, highlights: &[&Regex]) -> String {
+    let mut spans: Vec<(usize, usize, Option<Color>)> = highlights
+        .iter()
+        .flat_map(|re| re.find_iter(text))
+        .filter(|m| !m.is_empty())
+        .map(|m| (m.start(), m.end(), None))
+        .collect();
+
+    for m in LEVEL_TOKENS.find_iter(text) {
+        if !spans.iter().any(|&(s, e, _)| m.start() < e && s < m.end()) {
+            spans.push((m.start(), m.end(), Some(level_color(m.as_str()))));
+        }
+    }
+
+    spans.sort_by_key(|&(start, end, _)| (start, std::cmp::Reverse(end)));
+
+    let mut colored = String::with_capacity(text.len() + spans.len() * 12);
+    let mut position = 0;
+    for (start, end, color) in spans {
+        // skip highlights nested in or overlapping one already painted
+        if start < position {
+            continue;
+        }
+        colored.push_str(&text[position..start]);
+        match color {
+            Some(color) => colored.push_str(&color_println_fmt(color, &text[start..end])),
+            None => colored.push_str(&highlight_fmt(&text[start..end])),
+        }
+        position = end;
+    }
+    colored.push_str(&text[position..]);
+
+    colored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red(text: &str) -> String {
+        color_println_fmt(Color::Red, text)
+    }
+
+    #[test]
+    fn char_width_counts_wide_and_zero_width_characters() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('é'), 1);
+        assert_eq!(char_width('漢'), 2);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('😀'), 2);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('\u{fe0f}'), 0);
+        assert_eq!(char_width('\t'), 0);
+    }
+
+    #[test]
+    fn visible_width_skips_escape_sequences() {
+        assert_eq!(visible_width(""), 0);
+        assert_eq!(visible_width("plain"), 5);
+        assert_eq!(visible_width(&red("error")), 5);
+        assert_eq!(visible_width(&format!("a{}漢😀", red("b"))), 6);
+        assert_eq!(visible_width("e\u{301}"), 1);
+        assert_eq!(visible_width("\x1b7saved"), 5);
+    }
+
+    #[test]
+    fn truncate_keeps_short_text() {
+        assert_eq!(truncate("web", 3), "web");
+        assert_eq!(truncate(&red("web"), 3), red("web"));
+    }
+
+    #[test]
+    fn truncate_cuts_with_an_ellipsis() {
+        assert_eq!(truncate("container", 5), "cont…");
+        assert_eq!(truncate("container", 1), "…");
+        assert_eq!(truncate("container", 0), "");
+    }
+
+    #[test]
+    fn truncate_never_splits_escape_sequences() {
+        let truncated = truncate(&format!("{}-sonarr", red("media")), 6);
+
+        assert_eq!(truncated, format!("{}…{ANSI_RESET}", red("media")));
+        assert_eq!(visible_width(&truncated), 6);
+
+        // a sequence after the cut is kept whole, so colors are still reset
+        let truncated = truncate(&format!("ab{}", red("cdef")), 3);
+        assert_eq!(truncated, format!("ab{}…{ANSI_RESET}", Color::Red.code()));
+    }
+
+    #[test]
+    fn truncate_never_splits_wide_characters() {
+        // "漢字" does not fit with the ellipsis in 4 columns, only "漢" does
+        assert_eq!(truncate("漢字テスト", 4), "漢…");
+        assert_eq!(visible_width(&truncate("漢字テスト", 4)), 3);
+        assert_eq!(truncate("😀😀😀", 5), "😀😀…");
+        assert_eq!(truncate("a😀b", 2), "a…");
+    }
+
+    #[test]
+    fn table_aligns_cells_by_visible_width() {
+        let mut table = Table::new(vec!["NAME".to_string(), "STATUS".to_string()]);
+        table.row(vec![red("web"), "running".to_string()]);
+        table.row(vec!["漢字db".to_string(), red("exited")]);
+        table.row(vec!["proxy".to_string()]);
+
+        let rendered = table.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "NAME    STATUS");
+        assert_eq!(lines[1], format!("{}     running", red("web")));
+        assert_eq!(lines[2], format!("漢字db  {}", red("exited")));
+        assert_eq!(lines[3], "proxy");
+        assert!(lines.iter().all(|l| visible_width(l) <= 15));
+    }
+
+    #[test]
+    fn table_draws_borders_and_spanning_text() {
+        let mut table = Table::new(vec!["A".to_string(), "B".to_string()]).border(true);
+        table.text("media".to_string());
+        table.row(vec!["1".to_string(), red("22")]);
+
+        assert_eq!(
+            table.render(),
+            format!(
+                "┌───┬────┐\n\
+                 │ A │ B  │\n\
+                 ├───┼────┤\n\
+                 │ media  │\n\
+                 │ 1 │ {} │\n\
+                 └───┴────┘\n",
+                red("22")
+            )
+        );
+    }
+
+    #[test]
+    fn table_shrinks_the_widest_column_to_fit() {
+        let mut table =
+            Table::new(vec!["NAME".to_string(), "IMAGE".to_string()]).max_width(Some(20));
+        table.row(vec![
+            "web".to_string(),
+            "ghcr.io/example/web:latest".to_string(),
+        ]);
+
+        let rendered = table.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[1], "web   ghcr.io/examp…");
+        assert!(lines.iter().all(|l| visible_width(l) <= 20));
+    }
+}
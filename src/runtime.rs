@@ -0,0 +1,528 @@
+//! Container runtime abstraction
+//!
+//! Every docker operation dsd-util performs goes through [`ContainerRuntime`], so commands
+//! can run against the docker CLI on a real host or against [`FakeRuntime`] in tests.
+
+mod auth;
+mod docker;
+mod engine;
+mod fake;
+
+pub use docker::DockerCli;
+pub use engine::EngineApi;
+pub use fake::{FakeCall, FakeContainer, FakeRuntime};
+
+use crate::deployer::{resolve_host_sock, DOCKER_SOCK};
+use crate::http::{BodyDecoder, BodyFraming};
+use crate::models::{ContainerEvent, ContainerSummary};
+use crate::utils::{find_in_path, InspectData, StatsData};
+use chrono::{DateTime, Utc};
+use engine::FrameDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+
+/// How dsd-util talks to the container engine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transport {
+    /// Use the Engine API when its socket is reachable, the CLI otherwise
+    #[default]
+    Auto,
+    /// Spawn the docker CLI for every operation
+    Cli,
+    /// Talk HTTP to the Engine API socket directly
+    Api,
+}
+
+/// Which container engine dsd-util manages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RuntimeKind {
+    /// Detect from DOCKER_HOST/CONTAINER_HOST, the available sockets, then the PATH
+    #[default]
+    Auto,
+    Docker,
+    Podman,
+}
+
+impl RuntimeKind {
+    /// Resolves `Auto` to a concrete runtime, leaving explicit choices untouched
+    pub fn detect(self) -> RuntimeKind {
+        if self != RuntimeKind::Auto {
+            return self;
+        }
+
+        if let Ok(host) = std::env::var("DOCKER_HOST") {
+            // podman users commonly point DOCKER_HOST at the podman socket
+            return if host.contains("podman") {
+                RuntimeKind::Podman
+            } else {
+                RuntimeKind::Docker
+            };
+        }
+
+        if std::env::var("CONTAINER_HOST").is_ok() {
+            return RuntimeKind::Podman;
+        }
+
+        if std::path::Path::new(DOCKER_SOCK).exists() {
+            return RuntimeKind::Docker;
+        }
+
+        let podman_sock_exists = resolve_host_sock(RuntimeKind::Podman)
+            .is_ok_and(|sock| std::path::Path::new(&sock).exists());
+        if podman_sock_exists || (!find_in_path("docker") && find_in_path("podman")) {
+            return RuntimeKind::Podman;
+        }
+
+        RuntimeKind::Docker
+    }
+}
+
+/// Creates the runtime for the requested engine and transport
+pub fn connect(
+    kind: RuntimeKind,
+    transport: Transport,
+) -> anyhow::Result<Box<dyn ContainerRuntime>> {
+    let kind = kind.detect();
+    let cli = DockerCli::for_kind(kind);
+
+    match transport {
+        Transport::Cli => Ok(Box::new(cli)),
+        Transport::Api => Ok(Box::new(EngineApi::new(&resolve_host_sock(kind)?, cli))),
+        Transport::Auto => {
+            // a host the API client can't talk to (e.g. tcp://) is left to the CLI
+            let engine = resolve_host_sock(kind)
+                .map(|sock| EngineApi::new(&sock, cli.clone()))
+                .ok()
+                .filter(EngineApi::is_reachable);
+            match engine {
+                Some(engine) => Ok(Box::new(engine)),
+                None => Ok(Box::new(cli)),
+            }
+        }
+    }
+}
+
+/// Outcome of pulling an image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PullResult {
+    /// Whether a newer image was downloaded
+    pub updated: bool,
+    /// Digest of the pulled image, when the registry reported one
+    pub digest: Option<String>,
+}
+
+/// Operations dsd-util needs from a container runtime
+pub trait ContainerRuntime {
+    /// Lists running containers, or every container when `all` is set
+    fn list(&self, all: bool) -> anyhow::Result<Vec<ContainerSummary>>;
+
+    /// Inspects the given containers by name or id
+    fn inspect(&self, containers: &[String]) -> anyhow::Result<Vec<InspectData>>;
+
+    /// Takes a single snapshot of resource usage for the given containers
+    fn stats(&self, containers: &[String]) -> anyhow::Result<Vec<StatsData>>;
+
+    /// Streams the logs of a container
+    fn logs(&self, container: &str, options: &LogOptions) -> anyhow::Result<LogStream>;
+
+    /// Restarts a container
+    fn restart(&self, container: &str) -> anyhow::Result<()>;
+
+    /// Pulls an image, handing each progress message to `progress`
+    fn pull(&self, image: &str, progress: &mut dyn FnMut(&str)) -> anyhow::Result<PullResult>;
+
+    /// Force removes the given containers
+    fn remove(&self, containers: &[String]) -> anyhow::Result<()>;
+
+    /// Brings up a compose project in the background
+    fn compose_up(&self, compose_file: &str) -> anyhow::Result<()>;
+
+    /// Streams container events with one of the given actions (e.g. `start`) as they happen
+    fn events(&self, actions: &[&str]) -> anyhow::Result<EventStream>;
+}
+
+/// Which part of a container's logs to stream
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Number of lines to show from the end of the logs, all lines when None
+    pub tail: Option<u32>,
+    /// Only lines at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only lines at or before this time
+    pub until: Option<DateTime<Utc>>,
+    /// Keep streaming new lines instead of exiting at the end of the logs
+    pub follow: bool,
+    /// Prefix every line with the RFC3339 time docker received it
+    pub timestamps: bool,
+}
+
+impl LogOptions {
+    /// The `tail` argument as docker expects it
+    pub fn tail_arg(&self) -> String {
+        self.tail
+            .map_or_else(|| "all".to_string(), |t| t.to_string())
+    }
+}
+
+/// Which output of a container log bytes were written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Stdout,
+    Stderr,
+}
+
+/// How the bytes read from a [`LogSource`] are turned into container output
+enum Decoder {
+    /// The source carries a single output as is
+    Plain(StreamKind),
+    /// An Engine API log response body, multiplexed unless the container has a tty
+    Engine {
+        body: BodyDecoder,
+        frames: Option<FrameDecoder>,
+        decoded: Vec<u8>,
+    },
+}
+
+/// A non-blocking file descriptor of a [`LogStream`], read whenever it is ready
+pub struct LogSource {
+    file: File,
+    decoder: Decoder,
+    /// Bytes received before the source was handed out, decoded on the first read
+    buffered: Vec<u8>,
+}
+
+impl LogSource {
+    fn new(fd: OwnedFd, decoder: Decoder, buffered: Vec<u8>) -> io::Result<Self> {
+        set_nonblocking(fd.as_fd())?;
+
+        Ok(LogSource {
+            file: File::from(fd),
+            decoder,
+            buffered,
+        })
+    }
+
+    /// A source carrying one output of a container unframed, e.g. a `docker logs` pipe
+    fn plain(fd: OwnedFd, kind: StreamKind) -> io::Result<Self> {
+        LogSource::new(fd, Decoder::Plain(kind), vec![])
+    }
+
+    /// A source reading the body of an Engine API log response straight off the socket
+    pub(crate) fn engine(
+        socket: UnixStream,
+        buffered: Vec<u8>,
+        framing: BodyFraming,
+        multiplexed: bool,
+    ) -> io::Result<Self> {
+        let decoder = Decoder::Engine {
+            body: BodyDecoder::new(framing),
+            frames: multiplexed.then(FrameDecoder::default),
+            decoded: vec![],
+        };
+
+        LogSource::new(socket.into(), decoder, buffered)
+    }
+
+    /// Reads what is available into `buf` once, handing decoded output to `emit`
+    ///
+    /// Returns false once the source has ended. Reading when nothing is available is
+    /// not an error, so sources can be read right after being opened.
+    pub fn read(
+        &mut self,
+        buf: &mut [u8],
+        emit: &mut dyn FnMut(StreamKind, &[u8]),
+    ) -> io::Result<bool> {
+        if !self.buffered.is_empty() {
+            let buffered = std::mem::take(&mut self.buffered);
+            return self.decode(&buffered, emit);
+        }
+
+        match self.file.read(buf) {
+            Ok(0) => Ok(false),
+            Ok(n) => self.decode(&buf[..n], emit),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn decode(
+        &mut self,
+        bytes: &[u8],
+        emit: &mut dyn FnMut(StreamKind, &[u8]),
+    ) -> io::Result<bool> {
+        match &mut self.decoder {
+            Decoder::Plain(kind) => {
+                emit(*kind, bytes);
+                Ok(true)
+            }
+            Decoder::Engine {
+                body,
+                frames,
+                decoded,
+            } => {
+                decoded.clear();
+                body.decode(bytes, decoded)?;
+                match frames {
+                    Some(frames) => frames.decode(decoded, emit),
+                    // tty containers have a single raw stream
+                    None => emit(StreamKind::Stdout, decoded),
+                }
+                Ok(!body.is_done())
+            }
+        }
+    }
+}
+
+impl AsFd for LogSource {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+/// Switches a file descriptor to non-blocking reads
+fn set_nonblocking(fd: BorrowedFd<'_>) -> io::Result<()> {
+    let raw = fd.as_raw_fd();
+    // SAFETY: fcntl on a descriptor we borrow, with flags it just returned
+    let flags = unsafe { libc::fcntl(raw, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(raw, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Output of a container's logs, read either through blocking readers or by polling
+/// its [`LogSource`]s
+///
+/// When the stream is backed by a child process or socket, the process is killed or
+/// the socket shut down once the stream is dropped.
+pub struct LogStream {
+    pub stdout: Option<Box<dyn Read + Send>>,
+    pub stderr: Option<Box<dyn Read + Send>>,
+    /// Sources ready for polling, in place of the readers
+    sources: Vec<LogSource>,
+    /// Descriptors of the child's pipes, turned into sources on demand
+    pipes: Vec<(StreamKind, OwnedFd)>,
+    child: Option<Child>,
+    socket: Option<UnixStream>,
+}
+
+impl LogStream {
+    /// Builds a log stream from a spawned child with piped stdout/stderr
+    pub fn from_child(mut child: Child) -> Self {
+        let mut pipes = vec![];
+        if let Some(fd) = child
+            .stdout
+            .as_ref()
+            .and_then(|s| s.as_fd().try_clone_to_owned().ok())
+        {
+            pipes.push((StreamKind::Stdout, fd));
+        }
+        if let Some(fd) = child
+            .stderr
+            .as_ref()
+            .and_then(|s| s.as_fd().try_clone_to_owned().ok())
+        {
+            pipes.push((StreamKind::Stderr, fd));
+        }
+
+        let stdout = child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+
+        LogStream {
+            stdout,
+            stderr,
+            sources: vec![],
+            pipes,
+            child: Some(child),
+            socket: None,
+        }
+    }
+
+    /// Builds a log stream from in-memory or otherwise already open readers
+    pub fn from_readers(
+        stdout: Option<Box<dyn Read + Send>>,
+        stderr: Option<Box<dyn Read + Send>>,
+    ) -> Self {
+        LogStream {
+            stdout,
+            stderr,
+            sources: vec![],
+            pipes: vec![],
+            child: None,
+            socket: None,
+        }
+    }
+
+    /// Builds a log stream that can only be polled
+    pub(crate) fn from_sources(sources: Vec<LogSource>) -> Self {
+        LogStream {
+            stdout: None,
+            stderr: None,
+            sources,
+            pipes: vec![],
+            child: None,
+            socket: None,
+        }
+    }
+
+    /// Takes the sources to poll for this stream's output
+    ///
+    /// Readers without a file descriptor, e.g. in-memory ones, are copied into a pipe
+    /// by a thread of their own. The stream itself must be kept until polling ends.
+    pub fn take_sources(&mut self) -> io::Result<Vec<LogSource>> {
+        let mut sources = std::mem::take(&mut self.sources);
+
+        if !self.pipes.is_empty() {
+            // the readers share the pipes, drop them so only the sources read
+            self.stdout = None;
+            self.stderr = None;
+            for (kind, fd) in std::mem::take(&mut self.pipes) {
+                sources.push(LogSource::plain(fd, kind)?);
+            }
+        }
+
+        for (kind, reader) in [
+            (StreamKind::Stdout, self.stdout.take()),
+            (StreamKind::Stderr, self.stderr.take()),
+        ] {
+            let Some(mut reader) = reader else {
+                continue;
+            };
+            let (pipe_reader, mut pipe_writer) = io::pipe()?;
+            std::thread::spawn(move || io::copy(&mut reader, &mut pipe_writer));
+            sources.push(LogSource::plain(pipe_reader.into(), kind)?);
+        }
+
+        Ok(sources)
+    }
+
+    /// Ties the stream to a socket that is shut down when the stream is dropped
+    pub fn with_socket(mut self, socket: UnixStream) -> Self {
+        self.socket = Some(socket);
+        self
+    }
+}
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if let Some(socket) = self.socket.as_ref() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Container events as they are reported by the runtime
+///
+/// Lines that are not valid events are skipped. The underlying process or socket is
+/// stopped when the stream is dropped, or earlier through an [`EventStopper`].
+pub struct EventStream {
+    events: Box<dyn Iterator<Item = ContainerEvent> + Send>,
+    source: Arc<Mutex<Option<LogStream>>>,
+}
+
+/// Stops an [`EventStream`] being read on another thread, ending its iteration
+#[derive(Clone)]
+pub struct EventStopper(Arc<Mutex<Option<LogStream>>>);
+
+impl EventStopper {
+    /// Kills the events process or shuts the socket down
+    pub fn stop(&self) {
+        if let Ok(mut source) = self.0.lock() {
+            source.take();
+        }
+    }
+}
+
+impl EventStream {
+    /// Builds an event stream from a stream of JSON encoded events, one per line
+    pub fn from_json_lines(mut stream: LogStream) -> Self {
+        let events = stream.stdout.take().map(|stdout| {
+            BufReader::new(stdout)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str::<ContainerEvent>(&line).ok())
+        });
+
+        EventStream {
+            events: match events {
+                Some(events) => Box::new(events),
+                None => Box::new(std::iter::empty()),
+            },
+            source: Arc::new(Mutex::new(Some(stream))),
+        }
+    }
+
+    /// Builds an event stream from events already in memory
+    pub fn from_events(events: Vec<ContainerEvent>) -> Self {
+        EventStream {
+            events: Box::new(events.into_iter()),
+            source: Arc::default(),
+        }
+    }
+
+    /// Returns a handle stopping this stream from another thread
+    pub fn stopper(&self) -> EventStopper {
+        EventStopper(Arc::clone(&self.source))
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = ContainerEvent;
+
+    fn next(&mut self) -> Option<ContainerEvent> {
+        self.events.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn event_stopper_kills_the_events_process() {
+        let child = Command::new("sh")
+            .args(["-c", r#"echo '{"Action":"start"}'; exec sleep 30"#])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let events = EventStream::from_json_lines(LogStream::from_child(child));
+        let stopper = events.stopper();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let reader = std::thread::spawn(move || {
+            for event in events {
+                tx.send(event.action).unwrap();
+            }
+        });
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "start");
+        let started = Instant::now();
+        stopper.stop();
+        reader.join().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,280 @@
+//! Registry credentials for Engine API pulls, read the way the docker CLI reads them
+//!
+//! Credentials come from the `auths` of the docker config, or from the credential
+//! helper configured for the registry (`credHelpers`) or for every registry
+//! (`credsStore`).
+
+use anyhow::Context;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Key the docker CLI stores Docker Hub credentials under
+const DOCKER_HUB: &str = "https://index.docker.io/v1/";
+
+/// Username credential helpers return for identity tokens
+const TOKEN_USERNAME: &str = "<token>";
+
+/// Subset of `~/.docker/config.json`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct DockerConfig {
+    auths: BTreeMap<String, AuthEntry>,
+    creds_store: Option<String>,
+    cred_helpers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AuthEntry {
+    /// base64 of `username:password`
+    auth: Option<String>,
+    identitytoken: Option<String>,
+}
+
+/// Output of `docker-credential-<helper> get`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+/// Credentials as the engine expects them in the `X-Registry-Auth` header
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct RegistryAuth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identitytoken: Option<String>,
+    serveraddress: String,
+}
+
+impl RegistryAuth {
+    /// Encodes the credentials as the value of the `X-Registry-Auth` header
+    pub(crate) fn header(&self) -> anyhow::Result<String> {
+        Ok(URL_SAFE.encode(serde_json::to_vec(self)?))
+    }
+}
+
+impl DockerConfig {
+    /// Reads the config from $DOCKER_CONFIG or ~/.docker, empty when there is none
+    pub(crate) fn load() -> anyhow::Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(DockerConfig::default());
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Ok(DockerConfig::default());
+        };
+
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Finds the credentials for the registry `image` is pulled from, None to pull
+    /// anonymously
+    pub(crate) fn credentials(&self, image: &str) -> anyhow::Result<Option<RegistryAuth>> {
+        let registry = registry_of(image);
+
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(server, _)| same_registry(server, registry))
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+        if let Some(helper) = helper {
+            return helper_credentials(helper, registry);
+        }
+
+        let Some((server, entry)) = self
+            .auths
+            .iter()
+            .find(|(server, _)| same_registry(server, registry))
+        else {
+            return Ok(None);
+        };
+
+        if let Some(token) = &entry.identitytoken {
+            return Ok(Some(RegistryAuth {
+                identitytoken: Some(token.clone()),
+                serveraddress: server.clone(),
+                ..Default::default()
+            }));
+        }
+
+        let Some(auth) = &entry.auth else {
+            return Ok(None);
+        };
+        let decoded = STANDARD
+            .decode(auth.trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .with_context(|| format!("Invalid auth for {server} in the docker config"))?;
+        let (username, password) = decoded
+            .split_once(':')
+            .with_context(|| format!("Invalid auth for {server} in the docker config"))?;
+
+        Ok(Some(RegistryAuth {
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            serveraddress: server.clone(),
+            ..Default::default()
+        }))
+    }
+}
+
+/// Path of the docker CLI config
+fn config_path() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("DOCKER_CONFIG") {
+        return Some(PathBuf::from(dir).join("config.json"));
+    }
+
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker/config.json"))
+}
+
+/// Registry an image is pulled from, as keyed in the docker config
+///
+/// Like the docker CLI, the first path component names a registry only when it looks
+/// like a host, anything else is on Docker Hub.
+fn registry_of(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+        _ => DOCKER_HUB,
+    }
+}
+
+/// Compares a docker config key such as `https://ghcr.io` against a registry host
+fn same_registry(server: &str, registry: &str) -> bool {
+    let host = |s: &str| {
+        let s = s
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        let host = s.split('/').next().unwrap_or_default();
+        match host {
+            "docker.io" | "index.docker.io" | "registry-1.docker.io" => "index.docker.io",
+            host => host,
+        }
+        .to_string()
+    };
+
+    host(server) == host(registry)
+}
+
+/// Asks `docker-credential-<helper>` for the credentials of a registry
+fn helper_credentials(helper: &str, registry: &str) -> anyhow::Result<Option<RegistryAuth>> {
+    let binary = format!("docker-credential-{helper}");
+    let mut child = Command::new(&binary)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run credential helper {binary}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(registry.as_bytes())
+            .with_context(|| format!("Failed to query credential helper {binary}"))?;
+    }
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to run credential helper {binary}"))?;
+
+    // helpers exit non-zero when they have nothing stored for the registry
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let credentials: HelperCredentials = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Failed to parse the output of {binary}"))?;
+
+    let auth = if credentials.username == TOKEN_USERNAME {
+        RegistryAuth {
+            identitytoken: Some(credentials.secret),
+            serveraddress: registry.to_string(),
+            ..Default::default()
+        }
+    } else {
+        RegistryAuth {
+            username: Some(credentials.username),
+            password: Some(credentials.secret),
+            serveraddress: registry.to_string(),
+            ..Default::default()
+        }
+    };
+
+    Ok(Some(auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> DockerConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn registry_of_treats_hosts_as_registries() {
+        assert_eq!(registry_of("nginx"), DOCKER_HUB);
+        assert_eq!(registry_of("library/nginx:1.27"), DOCKER_HUB);
+        assert_eq!(registry_of("ghcr.io/wez/docker-stack-deploy"), "ghcr.io");
+        assert_eq!(registry_of("localhost/app"), "localhost");
+        assert_eq!(registry_of("registry:5000/app"), "registry:5000");
+    }
+
+    #[test]
+    fn credentials_decode_inline_auths() {
+        // "user:pa:ss"
+        let config = config(r#"{"auths":{"https://ghcr.io":{"auth":"dXNlcjpwYTpzcw=="}}}"#);
+
+        assert_eq!(
+            config.credentials("ghcr.io/wez/app").unwrap(),
+            Some(RegistryAuth {
+                username: Some("user".to_string()),
+                password: Some("pa:ss".to_string()),
+                serveraddress: "https://ghcr.io".to_string(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(config.credentials("nginx").unwrap(), None);
+    }
+
+    #[test]
+    fn credentials_match_docker_hub_aliases() {
+        let config = config(r#"{"auths":{"docker.io":{"identitytoken":"tok"}}}"#);
+
+        let auth = config.credentials("nginx").unwrap().unwrap();
+
+        assert_eq!(auth.identitytoken.as_deref(), Some("tok"));
+        assert_eq!(auth.username, None);
+    }
+
+    #[test]
+    fn credentials_reject_malformed_auths() {
+        let config = config(r#"{"auths":{"ghcr.io":{"auth":"bm9jb2xvbg=="}}}"#);
+
+        assert!(config.credentials("ghcr.io/app").is_err());
+    }
+
+    #[test]
+    fn header_is_url_safe_base64_json() {
+        let auth = RegistryAuth {
+            identitytoken: Some("tok".to_string()),
+            serveraddress: "ghcr.io".to_string(),
+            ..Default::default()
+        };
+
+        let decoded = URL_SAFE.decode(auth.header().unwrap()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(decoded).unwrap(),
+            r#"{"identitytoken":"tok","serveraddress":"ghcr.io"}"#
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use super::{ContainerRuntime, EventStream, LogOptions, LogStream, PullResult, RuntimeKind};
+use crate::models::{ContainerInspect, ContainerSummary};
+use crate::utils::{
+    find_in_path, parse_inspect_data, parse_stats_data, unix_timestamp, InspectData, StatsData,
+};
+use anyhow::Context;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+pub const DOCKER: &str = "docker";
+pub const PODMAN: &str = "podman";
+const PODMAN_COMPOSE: &str = "podman-compose";
+
+/// Container runtime backed by the docker CLI, or the docker-compatible podman CLI
+#[derive(Debug, Clone)]
+pub struct DockerCli {
+    binary: &'static str,
+    compose: OnceLock<Vec<&'static str>>,
+}
+
+impl Default for DockerCli {
+    fn default() -> Self {
+        DockerCli::new()
+    }
+}
+
+impl DockerCli {
+    pub fn new() -> Self {
+        DockerCli {
+            binary: DOCKER,
+            compose: OnceLock::new(),
+        }
+    }
+
+    /// Creates the CLI runtime for a detected runtime kind
+    pub fn for_kind(kind: RuntimeKind) -> Self {
+        match kind {
+            RuntimeKind::Podman => DockerCli {
+                binary: PODMAN,
+                compose: OnceLock::new(),
+            },
+            _ => DockerCli::new(),
+        }
+    }
+
+    /// Name of the CLI binary, `docker` or `podman`
+    pub fn binary(&self) -> &'static str {
+        self.binary
+    }
+
+    /// Returns true if the CLI binary is on the PATH
+    pub fn is_installed(&self) -> bool {
+        find_in_path(self.binary)
+    }
+
+    fn command(&self) -> Command {
+        Command::new(self.binary)
+    }
+
+    /// Builds a compose command: `docker compose`, `podman compose` or `podman-compose`
+    ///
+    /// `podman compose` needs an external compose provider, so it's probed once and
+    /// `podman-compose` is used directly when the probe fails.
+    fn compose_command(&self) -> Command {
+        let compose = self.compose.get_or_init(|| {
+            if self.binary != PODMAN {
+                return vec![self.binary, "compose"];
+            }
+
+            let has_provider = Command::new(PODMAN)
+                .args(["compose", "version"])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success());
+
+            if has_provider || !find_in_path(PODMAN_COMPOSE) {
+                vec![PODMAN, "compose"]
+            } else {
+                vec![PODMAN_COMPOSE]
+            }
+        });
+
+        let mut command = Command::new(compose[0]);
+        command.args(&compose[1..]);
+        command
+    }
+
+    /// Returns the ID of a local image, None if it isn't pulled yet
+    fn image_id(&self, image: &str) -> anyhow::Result<Option<String>> {
+        let output = self
+            .command()
+            .args(["image", "inspect", "--format", "{{.Id}}", image])
+            .output()
+            .context(format!("Failed to inspect image: {image}"))?;
+
+        // both CLIs fail with "no such image" for images that were never pulled
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((!id.is_empty()).then_some(id))
+    }
+}
+
+/// Fails with what the CLI printed to stderr when it exited non-zero
+fn check_status(output: &Output, action: &str) -> anyhow::Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match stderr.trim() {
+        "" => anyhow::bail!("{action}: exited with {}", output.status),
+        stderr => anyhow::bail!("{action}: {stderr}"),
+    }
+}
+
+impl ContainerRuntime for DockerCli {
+    fn list(&self, all: bool) -> anyhow::Result<Vec<ContainerSummary>> {
+        // Use docker to list container_ids
+        let mut ps = self.command();
+        ps.args(["ps", "-q", "--no-trunc"]);
+        if all {
+            ps.arg("-a");
+        }
+        let output = ps.output().context("Failed to list docker containers")?;
+        check_status(&output, "Failed to list docker containers")?;
+
+        // Turn Output into String
+        let container_id_list = String::from_utf8(output.stdout)
+            .context("Failed to create string of container id's")?;
+
+        let ids = container_id_list
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // labels are only reliably parseable from inspect output
+        let output = self
+            .command()
+            .arg("inspect")
+            .args(&ids)
+            .output()
+            .context("Failed to inspect containers")?;
+
+        let inspect_string = String::from_utf8(output.stdout)?;
+        if inspect_string.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let inspected: Vec<ContainerInspect> =
+            serde_json::from_str(&inspect_string).context("Failed to parse inspect output")?;
+
+        Ok(inspected.into_iter().map(ContainerSummary::from).collect())
+    }
+
+    fn inspect(&self, containers: &[String]) -> anyhow::Result<Vec<InspectData>> {
+        // missing containers make docker exit non-zero, but the rest are still printed
+        let output = self
+            .command()
+            .arg("inspect")
+            .args(containers)
+            .output()
+            .context("Failed to inspect containers")?;
+
+        let inspect_string = String::from_utf8(output.stdout)?;
+
+        parse_inspect_data(&inspect_string)
+    }
+
+    fn stats(&self, containers: &[String]) -> anyhow::Result<Vec<StatsData>> {
+        let output = self
+            .command()
+            .args(["stats", "--no-stream", "--format", "{{json .}}"])
+            .args(containers)
+            .output()
+            .context("Failed to get stats for containers")?;
+
+        // docker prints nothing if any container is missing, so retry one by one
+        // to still report the ones that are left
+        if !output.status.success() && containers.len() > 1 {
+            let stats = containers
+                .iter()
+                .filter_map(|c| self.stats(std::slice::from_ref(c)).ok())
+                .flatten()
+                .collect();
+            return Ok(stats);
+        }
+
+        let stats_string = String::from_utf8(output.stdout)?;
+
+        stats_string
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_stats_data)
+            .collect()
+    }
+
+    fn logs(&self, container: &str, options: &LogOptions) -> anyhow::Result<LogStream> {
+        let mut logs = self.command();
+        logs.args(["logs", container, "--tail", &options.tail_arg()]);
+        if let Some(since) = &options.since {
+            logs.args(["--since", &unix_timestamp(since)]);
+        }
+        if let Some(until) = &options.until {
+            logs.args(["--until", &unix_timestamp(until)]);
+        }
+        if options.follow {
+            logs.arg("--follow");
+        }
+        if options.timestamps {
+            logs.arg("--timestamps");
+        }
+
+        let child = logs
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(format!("Failed to follow logs for {container}"))?;
+
+        Ok(LogStream::from_child(child))
+    }
+
+    fn restart(&self, container: &str) -> anyhow::Result<()> {
+        let output = self
+            .command()
+            .args(["restart", container])
+            .output()
+            .context(format!("Failed to restart {container}"))?;
+
+        check_status(&output, &format!("{} restart", self.binary))
+    }
+
+    /// Podman never prints docker's "Downloaded newer image", so an update is detected
+    /// by comparing the image ID before and after the pull
+    fn pull(&self, image: &str, progress: &mut dyn FnMut(&str)) -> anyhow::Result<PullResult> {
+        let mut result = PullResult::default();
+        let before = self.image_id(image)?;
+
+        let mut pull_process = self
+            .command()
+            .args(["pull", image])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(format!("Failed to pull image: {image}"))?;
+
+        if let Some(stdout) = pull_process.stdout.take() {
+            let reader = BufReader::new(stdout);
+            for line in reader.lines().map_while(Result::ok) {
+                progress(&line);
+                if let Some(digest) = line.strip_prefix("Digest: ") {
+                    result.digest = Some(digest.trim().to_string());
+                }
+            }
+        }
+
+        let output = pull_process
+            .wait_with_output()
+            .context(format!("Failed to pull image: {image}"))?;
+        check_status(&output, &format!("Failed to pull image: {image}"))?;
+
+        result.updated = self.image_id(image)? != before;
+
+        Ok(result)
+    }
+
+    fn remove(&self, containers: &[String]) -> anyhow::Result<()> {
+        let output = self
+            .command()
+            .args(["rm", "-f"])
+            .args(containers)
+            .output()
+            .context("Failed to remove containers")?;
+
+        check_status(&output, "Failed to remove containers")
+    }
+
+    fn compose_up(&self, compose_file: &str) -> anyhow::Result<()> {
+        let status = self
+            .compose_command()
+            .args(["-f", compose_file, "up", "-d"])
+            .status()
+            .context("Failed to start docker-stack-deploy")?;
+
+        if !status.success() {
+            anyhow::bail!("{} compose up -d failed with status {status}", self.binary);
+        }
+
+        Ok(())
+    }
+
+    fn events(&self, actions: &[&str]) -> anyhow::Result<EventStream> {
+        let mut events = self.command();
+        events.args([
+            "events",
+            "--format",
+            "{{json .}}",
+            "--filter",
+            "type=container",
+        ]);
+        for action in actions {
+            events.args(["--filter", &format!("event={action}")]);
+        }
+
+        let child = events
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to watch container events")?;
+
+        Ok(EventStream::from_json_lines(LogStream::from_child(child)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// A stand-in CLI in its own temp dir, keeping the local image ID in a file
+    ///
+    /// `pull` prints `output` and stores `pulled` as the new image ID, or fails when
+    /// `pulled` is empty.
+    struct StubCli {
+        dir: PathBuf,
+        cli: DockerCli,
+    }
+
+    impl StubCli {
+        fn new(name: &str, output: &str, pulled: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("dsd-util-cli-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            let pull = if pulled.is_empty() {
+                "echo 'Error: manifest unknown' >&2; exit 125".to_string()
+            } else {
+                format!("printf '%s\\n' '{output}'; echo '{pulled}' > \"$id\"")
+            };
+            let script = format!(
+                "#!/bin/sh\n\
+                 id=\"$(dirname \"$0\")/id\"\n\
+                 case \"$1\" in\n\
+                 image) [ -f \"$id\" ] && cat \"$id\" && exit 0; echo 'Error: no such image' >&2; exit 125 ;;\n\
+                 pull) {pull} ;;\n\
+                 esac\n"
+            );
+            let binary = dir.join("cli");
+            fs::write(&binary, script).unwrap();
+            fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+            let binary = binary.to_str().unwrap().to_string();
+            StubCli {
+                dir,
+                cli: DockerCli {
+                    binary: Box::leak(binary.into_boxed_str()),
+                    compose: OnceLock::new(),
+                },
+            }
+        }
+
+        fn with_image(self, id: &str) -> Self {
+            fs::write(self.dir.join("id"), format!("{id}\n")).unwrap();
+            self
+        }
+    }
+
+    impl Drop for StubCli {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    const PODMAN_OUTPUT: &str = "Trying to pull docker.io/library/nginx:latest...\n\
+                                 Writing manifest to image destination\n\
+                                 4f67c83422ec";
+
+    #[test]
+    fn pull_detects_updates_from_podman_by_image_id() {
+        let stub = StubCli::new("podman-new", PODMAN_OUTPUT, "sha256:new").with_image("sha256:old");
+        let mut lines = vec![];
+
+        let result = stub
+            .cli
+            .pull("nginx", &mut |line| lines.push(line.to_string()))
+            .unwrap();
+
+        assert!(result.updated);
+        assert_eq!(result.digest, None);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Trying to pull docker.io/library/nginx:latest...");
+    }
+
+    #[test]
+    fn pull_reports_unchanged_and_first_pulls() {
+        let unchanged =
+            StubCli::new("podman-same", PODMAN_OUTPUT, "sha256:same").with_image("sha256:same");
+        let first = StubCli::new("podman-first", PODMAN_OUTPUT, "sha256:new");
+
+        assert!(!unchanged.cli.pull("nginx", &mut |_| {}).unwrap().updated);
+        assert!(first.cli.pull("nginx", &mut |_| {}).unwrap().updated);
+    }
+
+    #[test]
+    fn pull_reads_the_digest_docker_prints() {
+        let output = "latest: Pulling from library/nginx\n\
+                      Digest: sha256:feed\n\
+                      Status: Image is up to date for nginx:latest";
+        let stub = StubCli::new("docker", output, "sha256:same").with_image("sha256:same");
+
+        let result = stub.cli.pull("nginx", &mut |_| {}).unwrap();
+
+        assert!(!result.updated);
+        assert_eq!(result.digest.as_deref(), Some("sha256:feed"));
+    }
+
+    #[test]
+    fn pull_reports_failures_with_stderr() {
+        let stub = StubCli::new("failing", "", "");
+
+        let error = stub.cli.pull("nginx", &mut |_| {}).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Failed to pull image: nginx: Error: manifest unknown"
+        );
+    }
+}
@@ -0,0 +1,793 @@
+use super::auth::DockerConfig;
+use super::{
+    ContainerRuntime, DockerCli, EventStream, LogOptions, LogSource, LogStream, PullResult,
+    StreamKind,
+};
+use crate::http::{self, encode, Response};
+use crate::models::{ContainerInspect, ContainerSummary};
+use crate::utils::{unix_timestamp, InspectData, StatsData};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+
+/// Host header sent to the engine; the socket path is what actually routes the request
+const API_HOST: &str = "docker";
+
+/// Container runtime that talks HTTP to the Docker Engine API over its unix socket
+///
+/// Compose has no Engine API endpoint, so `compose_up` still needs the docker or podman
+/// CLI installed.
+#[derive(Debug, Clone)]
+pub struct EngineApi {
+    sock: String,
+    cli: DockerCli,
+}
+
+/// Subset of `GET /containers/json` entries
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct ListedContainer {
+    id: String,
+    names: Vec<String>,
+    image: String,
+    state: String,
+    labels: Option<BTreeMap<String, String>>,
+}
+
+impl From<ListedContainer> for ContainerSummary {
+    fn from(listed: ListedContainer) -> Self {
+        ContainerSummary {
+            name: listed
+                .names
+                .first()
+                .map(|n| n.trim_start_matches('/').to_string())
+                .unwrap_or_else(|| listed.id.clone()),
+            id: listed.id,
+            image: listed.image,
+            state: listed.state,
+            labels: listed.labels.unwrap_or_default(),
+        }
+    }
+}
+
+/// Subset of `GET /containers/{id}/stats`
+#[derive(Debug, Deserialize)]
+struct ContainerStatsResponse {
+    name: String,
+    cpu_stats: CpuStats,
+    precpu_stats: CpuStats,
+    memory_stats: MemoryStats,
+    #[serde(default)]
+    networks: BTreeMap<String, NetworkStats>,
+    #[serde(default)]
+    blkio_stats: BlkioStats,
+    #[serde(default)]
+    pids_stats: PidsStats,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CpuStats {
+    #[serde(default)]
+    cpu_usage: CpuUsage,
+    system_cpu_usage: Option<u64>,
+    online_cpus: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CpuUsage {
+    #[serde(default)]
+    total_usage: u64,
+    percpu_usage: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MemoryStats {
+    usage: Option<u64>,
+    limit: Option<u64>,
+    #[serde(default)]
+    stats: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NetworkStats {
+    #[serde(default)]
+    rx_bytes: u64,
+    #[serde(default)]
+    tx_bytes: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct BlkioStats {
+    /// Null on cgroup v2 hosts without io accounting
+    io_service_bytes_recursive: Option<Vec<BlkioEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlkioEntry {
+    op: String,
+    value: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PidsStats {
+    current: Option<u64>,
+}
+
+/// One line of `POST /images/create` progress output
+#[derive(Debug, Deserialize)]
+struct PullProgress {
+    id: Option<String>,
+    status: Option<String>,
+    error: Option<String>,
+}
+
+/// Subset of `GET /images/{name}/json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ImageInspect {
+    id: String,
+}
+
+/// Error body returned by the engine for non-2xx responses
+#[derive(Debug, Deserialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+impl EngineApi {
+    /// Creates a client for the engine listening on the unix socket at `sock`,
+    /// using `cli` for compose operations
+    pub fn new(sock: &str, cli: DockerCli) -> Self {
+        EngineApi {
+            sock: sock.to_string(),
+            cli,
+        }
+    }
+
+    /// Returns true if the engine socket accepts connections
+    pub fn is_reachable(&self) -> bool {
+        UnixStream::connect(&self.sock).is_ok()
+    }
+
+    /// Sends a request on a fresh connection to the engine socket
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> anyhow::Result<Response<UnixStream>> {
+        let stream = UnixStream::connect(&self.sock)
+            .with_context(|| format!("Failed to connect to engine socket {}", self.sock))?;
+
+        let mut headers = headers.to_vec();
+        if body.is_some() {
+            headers.push(("Content-Type", "application/json"));
+        }
+
+        http::send(stream, method, API_HOST, path, &headers, body)
+            .with_context(|| format!("Engine API request failed: {method} {path}"))
+    }
+
+    /// Sends a request and fails with the engine's error message on non-2xx responses
+    fn request_ok(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&[u8]>,
+    ) -> anyhow::Result<Response<UnixStream>> {
+        let response = self.request(method, path, &[], body)?;
+
+        check_response(response, method, path)
+    }
+
+    /// Sends a request and deserializes its JSON body
+    fn get_json<T: for<'de> Deserialize<'de>>(&self, path: &str) -> anyhow::Result<T> {
+        let text = self.request_ok("GET", path, None)?.text()?;
+
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse response of {path}"))
+    }
+
+    fn inspect_one(&self, container: &str) -> anyhow::Result<ContainerInspect> {
+        self.get_json(&format!("/containers/{}/json", encode(container)))
+    }
+
+    /// Returns the ID of a local image, None if it isn't pulled yet
+    fn image_id(&self, image: &str) -> anyhow::Result<Option<String>> {
+        let path = format!("/images/{}/json", encode(image));
+        let response = self.request("GET", &path, &[], None)?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+
+        let text = check_response(response, "GET", &path)?.text()?;
+        let image: ImageInspect = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse response of {path}"))?;
+
+        Ok(Some(image.id))
+    }
+
+    fn stats_one(&self, container: &str) -> anyhow::Result<StatsData> {
+        let stats: ContainerStatsResponse = self.get_json(&format!(
+            "/containers/{}/stats?stream=false",
+            encode(container)
+        ))?;
+
+        Ok(to_stats_data(stats))
+    }
+}
+
+/// Fails with the engine's error message on non-2xx responses
+fn check_response(
+    response: Response<UnixStream>,
+    method: &str,
+    path: &str,
+) -> anyhow::Result<Response<UnixStream>> {
+    if !response.is_success() {
+        let status = response.status;
+        let text = response.text().unwrap_or_default();
+        let message = serde_json::from_str::<ErrorMessage>(&text)
+            .map(|e| e.message)
+            .unwrap_or(text);
+        anyhow::bail!(
+            "{method} {path} failed with status {status}: {}",
+            message.trim()
+        );
+    }
+
+    Ok(response)
+}
+
+/// Splits an image reference into the `fromImage` and `tag` query parameters
+fn split_image_reference(image: &str) -> (&str, &str) {
+    if let Some((name, digest)) = image.split_once('@') {
+        return (name, digest);
+    }
+
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    match image[name_start..].rfind(':') {
+        Some(i) => (&image[..name_start + i], &image[name_start + i + 1..]),
+        None => (image, "latest"),
+    }
+}
+
+/// Computes CPU and memory percentages the same way `docker stats` does
+fn to_stats_data(stats: ContainerStatsResponse) -> StatsData {
+    let cpu_delta = stats
+        .cpu_stats
+        .cpu_usage
+        .total_usage
+        .saturating_sub(stats.precpu_stats.cpu_usage.total_usage) as f64;
+    let system_delta = stats
+        .cpu_stats
+        .system_cpu_usage
+        .unwrap_or_default()
+        .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default())
+        as f64;
+    let online_cpus = stats.cpu_stats.online_cpus.unwrap_or_else(|| {
+        stats
+            .cpu_stats
+            .cpu_usage
+            .percpu_usage
+            .as_ref()
+            .map_or(1, |p| p.len() as u64)
+    }) as f64;
+
+    let cpu = if cpu_delta > 0.0 && system_delta > 0.0 {
+        cpu_delta / system_delta * online_cpus * 100.0
+    } else {
+        0.0
+    };
+
+    // page cache is not counted as used memory (cgroup v2 then v1 key)
+    let memory_stats = &stats.memory_stats;
+    let cache = ["inactive_file", "total_inactive_file"]
+        .iter()
+        .find_map(|key| memory_stats.stats.get(*key).copied())
+        .unwrap_or_default();
+    let used = memory_stats.usage.unwrap_or_default().saturating_sub(cache);
+    let memory = match memory_stats.limit {
+        Some(limit) if limit > 0 => used as f64 / limit as f64 * 100.0,
+        _ => 0.0,
+    };
+
+    StatsData {
+        container_name: stats.name.trim_start_matches('/').to_string(),
+        cpu: Some(cpu),
+        memory: Some(memory),
+        memory_bytes: memory_stats.usage.map(|_| used),
+        memory_limit: memory_stats.limit,
+        net_io: (!stats.networks.is_empty()).then(|| {
+            stats
+                .networks
+                .values()
+                .fold((0, 0), |(rx, tx), n| (rx + n.rx_bytes, tx + n.tx_bytes))
+        }),
+        block_io: stats
+            .blkio_stats
+            .io_service_bytes_recursive
+            .as_ref()
+            .map(|entries| {
+                // the op is capitalized on cgroup v1 and lowercase on v2
+                let total = |op: &str| {
+                    entries
+                        .iter()
+                        .filter(|e| e.op.eq_ignore_ascii_case(op))
+                        .map(|e| e.value)
+                        .sum()
+                };
+                (total("read"), total("write"))
+            }),
+        pids: stats.pids_stats.current,
+    }
+}
+
+/// Splits the engine's multiplexed log stream into stdout and stderr output
+///
+/// Each frame is an 8 byte header (stream type, 3 padding bytes, big-endian u32 size)
+/// followed by the payload. Partial frames are kept until the rest arrives.
+#[derive(Debug, Default)]
+pub(crate) struct FrameDecoder {
+    pending: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub(crate) fn decode(&mut self, input: &[u8], emit: &mut dyn FnMut(StreamKind, &[u8])) {
+        self.pending.extend_from_slice(input);
+
+        let mut start = 0;
+        while let Some(header) = self.pending.get(start..start + 8) {
+            let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let Some(payload) = self.pending.get(start + 8..start + 8 + size) else {
+                break;
+            };
+
+            let kind = match header[0] {
+                2 => StreamKind::Stderr,
+                _ => StreamKind::Stdout,
+            };
+            emit(kind, payload);
+            start += 8 + size;
+        }
+
+        self.pending.drain(..start);
+    }
+}
+
+impl ContainerRuntime for EngineApi {
+    fn list(&self, all: bool) -> anyhow::Result<Vec<ContainerSummary>> {
+        let path = if all {
+            "/containers/json?all=1"
+        } else {
+            "/containers/json"
+        };
+
+        let containers: Vec<ListedContainer> = self.get_json(path)?;
+
+        Ok(containers.into_iter().map(ContainerSummary::from).collect())
+    }
+
+    fn inspect(&self, containers: &[String]) -> anyhow::Result<Vec<InspectData>> {
+        let mut inspected = Vec::with_capacity(containers.len());
+        for container in containers {
+            let path = format!("/containers/{}/json", encode(container));
+            let response = self.request("GET", &path, &[], None)?;
+
+            // like `docker inspect`, containers that no longer exist are skipped
+            if response.status == 404 {
+                continue;
+            }
+
+            let text = check_response(response, "GET", &path)
+                .and_then(Response::text)
+                .with_context(|| format!("Failed to inspect container {container}"))?;
+            let inspect: ContainerInspect = serde_json::from_str(&text)
+                .with_context(|| format!("Failed to parse inspect data of {container}"))?;
+            inspected.push(InspectData::from(inspect));
+        }
+
+        Ok(inspected)
+    }
+
+    fn stats(&self, containers: &[String]) -> anyhow::Result<Vec<StatsData>> {
+        // the engine samples for about a second per request, so sample in parallel
+        let results = std::thread::scope(|scope| {
+            let handles = containers
+                .iter()
+                .map(|c| scope.spawn(move || self.stats_one(c)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .filter_map(|h| h.join().ok())
+                .collect::<Vec<_>>()
+        });
+
+        Ok(results.into_iter().filter_map(Result::ok).collect())
+    }
+
+    fn logs(&self, container: &str, options: &LogOptions) -> anyhow::Result<LogStream> {
+        let tty = self.inspect_one(container)?.config.tty;
+
+        let mut path = format!(
+            "/containers/{}/logs?stdout=1&stderr=1&tail={}&follow={}&timestamps={}",
+            encode(container),
+            options.tail_arg(),
+            u8::from(options.follow),
+            u8::from(options.timestamps)
+        );
+        if let Some(since) = &options.since {
+            path.push_str(&format!("&since={}", unix_timestamp(since)));
+        }
+        if let Some(until) = &options.until {
+            path.push_str(&format!("&until={}", unix_timestamp(until)));
+        }
+
+        let response = self.request_ok("GET", &path, None)?;
+
+        let (socket, buffered, framing) = response.body.into_raw();
+        let shutdown = socket
+            .try_clone()
+            .context("Failed to clone engine socket")?;
+        let source = LogSource::engine(socket, buffered, framing, !tty)
+            .context("Failed to read engine log stream")?;
+
+        Ok(LogStream::from_sources(vec![source]).with_socket(shutdown))
+    }
+
+    fn restart(&self, container: &str) -> anyhow::Result<()> {
+        self.request_ok(
+            "POST",
+            &format!("/containers/{}/restart", encode(container)),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Pulls with the registry credentials from the docker config, like `docker pull`
+    fn pull(&self, image: &str, on_progress: &mut dyn FnMut(&str)) -> anyhow::Result<PullResult> {
+        let auth = DockerConfig::load()?
+            .credentials(image)
+            .with_context(|| format!("Failed to get registry credentials for {image}"))?
+            .map(|auth| auth.header())
+            .transpose()?;
+        let headers = match &auth {
+            Some(auth) => vec![("X-Registry-Auth", auth.as_str())],
+            None => vec![],
+        };
+
+        let before = self.image_id(image)?;
+
+        let (from_image, tag) = split_image_reference(image);
+        let path = format!(
+            "/images/create?fromImage={}&tag={}",
+            encode(from_image),
+            encode(tag)
+        );
+        let response = self.request("POST", &path, &headers, None)?;
+        let response = check_response(response, "POST", &path)?;
+
+        let mut result = PullResult::default();
+
+        let reader = BufReader::new(response.body);
+        for line in reader.lines().map_while(Result::ok) {
+            let Ok(progress) = serde_json::from_str::<PullProgress>(&line) else {
+                continue;
+            };
+
+            if let Some(error) = progress.error {
+                anyhow::bail!("Failed to pull image {image}: {error}");
+            }
+
+            let Some(status) = progress.status else {
+                continue;
+            };
+
+            // per-layer download progress is too noisy to print line by line
+            if status == "Downloading" || status == "Extracting" {
+                continue;
+            }
+
+            match &progress.id {
+                Some(id) => on_progress(&format!("{id}: {status}")),
+                None => on_progress(&status),
+            }
+
+            if let Some(digest) = status.strip_prefix("Digest: ") {
+                result.digest = Some(digest.trim().to_string());
+            }
+        }
+
+        // podman's compat API never reports "Downloaded newer image", compare IDs instead
+        result.updated = self.image_id(image)? != before;
+
+        Ok(result)
+    }
+
+    fn remove(&self, containers: &[String]) -> anyhow::Result<()> {
+        for container in containers {
+            self.request_ok(
+                "DELETE",
+                &format!("/containers/{}?force=1", encode(container)),
+                None,
+            )
+            .context("Failed to remove containers")?;
+        }
+
+        Ok(())
+    }
+
+    fn compose_up(&self, compose_file: &str) -> anyhow::Result<()> {
+        if !self.cli.is_installed() {
+            anyhow::bail!(
+                "The Engine API has no compose endpoint, `{} compose up` needs the {} CLI installed",
+                self.cli.binary(),
+                self.cli.binary()
+            );
+        }
+
+        self.cli.compose_up(compose_file)
+    }
+
+    fn events(&self, actions: &[&str]) -> anyhow::Result<EventStream> {
+        let filters = serde_json::json!({ "type": ["container"], "event": actions });
+        let response = self.request_ok(
+            "GET",
+            &format!("/events?filters={}", encode(&filters.to_string())),
+            None,
+        )?;
+
+        let socket = response
+            .body
+            .get_ref()
+            .try_clone()
+            .context("Failed to clone engine socket")?;
+
+        let stream = LogStream::from_readers(Some(Box::new(response.body)), None);
+
+        Ok(EventStream::from_json_lines(stream.with_socket(socket)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::{serve, serve_once};
+    use base64::engine::general_purpose::URL_SAFE;
+    use base64::Engine;
+
+    fn engine_with(response: &str) -> (EngineApi, std::thread::JoinHandle<String>) {
+        let (path, server) = serve_once(response.as_bytes());
+        (
+            EngineApi::new(path.to_str().unwrap(), DockerCli::new()),
+            server,
+        )
+    }
+
+    fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![kind, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn frame_decoder_joins_frames_split_across_reads() {
+        let mut input = frame(1, b"out\n");
+        input.extend(frame(2, b"err\n"));
+        input.extend(frame(1, b""));
+        input.extend(frame(1, b"more"));
+
+        for split in [1, 3, 8, 9, 100] {
+            let mut decoder = FrameDecoder::default();
+            let mut decoded = vec![];
+            for piece in input.chunks(split) {
+                decoder.decode(piece, &mut |kind, payload| {
+                    decoded.push((kind, payload.to_vec()))
+                });
+            }
+
+            let expected = [
+                (StreamKind::Stdout, b"out\n".to_vec()),
+                (StreamKind::Stderr, b"err\n".to_vec()),
+                (StreamKind::Stdout, vec![]),
+                (StreamKind::Stdout, b"more".to_vec()),
+            ];
+            assert_eq!(decoded, expected, "split every {split} bytes");
+        }
+    }
+
+    #[test]
+    fn list_parses_containers_from_the_socket() {
+        let body = r#"[{"Id":"aaa","Names":["/web"],"Image":"nginx","State":"running","Labels":{"com.docker.compose.project":"media"}},{"Id":"bbb","State":"exited","Labels":null}]"#;
+        let (engine, server) = engine_with(&format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+
+        let containers = engine.list(true).unwrap();
+
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /containers/json?all=1 HTTP/1.1\r\n"));
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].name, "web");
+        assert_eq!(containers[0].stack(), Some("media"));
+        assert!(containers[0].is_running());
+        assert_eq!(containers[1].name, "bbb");
+        assert!(containers[1].labels.is_empty());
+    }
+
+    #[test]
+    fn restart_reports_the_engine_error_message() {
+        let (engine, server) = engine_with(
+            "HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+             24\r\n{\"message\":\"No such container: db\"}\r\n0\r\n\r\n",
+        );
+
+        let error = engine.restart("media/db").unwrap_err();
+
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("POST /containers/media%2Fdb/restart "));
+        assert_eq!(
+            error.to_string(),
+            "POST /containers/media%2Fdb/restart failed with status 404: No such container: db"
+        );
+    }
+
+    fn json_response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn pull_sends_registry_credentials_and_compares_image_ids() {
+        let config = std::env::temp_dir().join(format!("dsd-util-test-{}", std::process::id()));
+        std::fs::create_dir_all(&config).unwrap();
+        // "user:secret"
+        std::fs::write(
+            config.join("config.json"),
+            r#"{"auths":{"ghcr.io":{"auth":"dXNlcjpzZWNyZXQ="}}}"#,
+        )
+        .unwrap();
+        // SAFETY: no other test reads DOCKER_CONFIG
+        unsafe { std::env::set_var("DOCKER_CONFIG", &config) };
+
+        let progress = "{\"status\":\"Pulling from wez/app\",\"id\":\"1.0\"}\n\
+                        {\"status\":\"Downloading\",\"id\":\"abc\"}\n\
+                        {\"status\":\"Digest: sha256:feed\"}\n";
+        let responses = [
+            json_response("200 OK", r#"{"Id":"sha256:old"}"#),
+            json_response("200 OK", progress),
+            json_response("200 OK", r#"{"Id":"sha256:new"}"#),
+        ];
+        let (path, server) = serve(&responses.each_ref().map(|r| r.as_bytes()));
+        let engine = EngineApi::new(path.to_str().unwrap(), DockerCli::new());
+
+        let mut lines = vec![];
+        let result = engine
+            .pull("ghcr.io/wez/app:1.0", &mut |line| {
+                lines.push(line.to_string())
+            })
+            .unwrap();
+
+        unsafe { std::env::remove_var("DOCKER_CONFIG") };
+        std::fs::remove_dir_all(&config).unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /images/ghcr.io%2Fwez%2Fapp%3A1.0/json "));
+        assert!(requests[1]
+            .starts_with("POST /images/create?fromImage=ghcr.io%2Fwez%2Fapp&tag=1.0 HTTP/1.1\r\n"));
+        let header = requests[1]
+            .lines()
+            .find_map(|l| l.strip_prefix("X-Registry-Auth: "))
+            .unwrap();
+        let auth = String::from_utf8(URL_SAFE.decode(header).unwrap()).unwrap();
+        assert_eq!(
+            auth,
+            r#"{"username":"user","password":"secret","serveraddress":"ghcr.io"}"#
+        );
+        assert_eq!(lines, ["1.0: Pulling from wez/app", "Digest: sha256:feed"]);
+        assert!(result.updated);
+        assert_eq!(result.digest.as_deref(), Some("sha256:feed"));
+    }
+
+    #[test]
+    fn pull_reports_the_first_pull_and_unchanged_images() {
+        let not_found = json_response("404 Not Found", r#"{"message":"No such image"}"#);
+        let status = json_response("200 OK", "{\"status\":\"Status: Image is up to date\"}\n");
+        let found = json_response("200 OK", r#"{"Id":"sha256:same"}"#);
+
+        for (before, updated) in [(&not_found, true), (&found, false)] {
+            let (path, server) = serve(&[before.as_bytes(), status.as_bytes(), found.as_bytes()]);
+            let engine = EngineApi::new(path.to_str().unwrap(), DockerCli::new());
+
+            let result = engine.pull("nginx", &mut |_| {}).unwrap();
+
+            let requests = server.join().unwrap();
+            assert!(requests[1].starts_with("POST /images/create?fromImage=nginx&tag=latest "));
+            assert_eq!(result.updated, updated);
+        }
+    }
+
+    #[test]
+    fn pull_fails_on_progress_errors() {
+        let responses = [
+            json_response("404 Not Found", "{}"),
+            json_response("200 OK", "{\"error\":\"manifest unknown\"}\n"),
+        ];
+        let (path, server) = serve(&responses.each_ref().map(|r| r.as_bytes()));
+        let engine = EngineApi::new(path.to_str().unwrap(), DockerCli::new());
+
+        let error = engine
+            .pull("localhost/app@sha256:abc", &mut |_| {})
+            .unwrap_err();
+
+        let requests = server.join().unwrap();
+        assert!(requests[1]
+            .starts_with("POST /images/create?fromImage=localhost%2Fapp&tag=sha256%3Aabc "));
+        assert_eq!(
+            error.to_string(),
+            "Failed to pull image localhost/app@sha256:abc: manifest unknown"
+        );
+    }
+
+    #[test]
+    fn split_image_reference_defaults_to_latest() {
+        assert_eq!(split_image_reference("nginx"), ("nginx", "latest"));
+        assert_eq!(
+            split_image_reference("registry:5000/app"),
+            ("registry:5000/app", "latest")
+        );
+        assert_eq!(
+            split_image_reference("registry:5000/app:2"),
+            ("registry:5000/app", "2")
+        );
+        assert_eq!(
+            split_image_reference("app@sha256:abc"),
+            ("app", "sha256:abc")
+        );
+    }
+
+    #[test]
+    fn inspect_skips_missing_containers_only() {
+        let inspect =
+            r#"{"Id":"aaa","Name":"/web","State":{"Status":"running"},"Config":{"Tty":false}}"#;
+        let responses = [
+            json_response("404 Not Found", r#"{"message":"No such container: gone"}"#),
+            json_response("200 OK", inspect),
+        ];
+        let (path, server) = serve(&responses.each_ref().map(|r| r.as_bytes()));
+        let engine = EngineApi::new(path.to_str().unwrap(), DockerCli::new());
+
+        let inspected = engine
+            .inspect(&["gone".to_string(), "web".to_string()])
+            .unwrap();
+
+        server.join().unwrap();
+        assert_eq!(inspected.len(), 1);
+        assert_eq!(inspected[0].container_name, "web");
+
+        let (engine, server) = engine_with(&json_response(
+            "500 Internal Server Error",
+            r#"{"message":"database is locked"}"#,
+        ));
+
+        let error = engine.inspect(&["web".to_string()]).unwrap_err();
+
+        server.join().unwrap();
+        assert_eq!(error.to_string(), "Failed to inspect container web");
+        assert_eq!(
+            error.root_cause().to_string(),
+            "GET /containers/web/json failed with status 500: database is locked"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use super::{ContainerRuntime, EventStream, LogOptions, LogStream, PullResult};
+use crate::models::{ContainerEvent, ContainerSummary, EventActor, SERVICE_LABEL, STACK_LABEL};
+use crate::utils::{InspectData, StatsData};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::sync::Mutex;
+
+/// A container known to the [`FakeRuntime`]
+#[derive(Debug, Clone)]
+pub struct FakeContainer {
+    pub id: String,
+    pub labels: BTreeMap<String, String>,
+    pub running: bool,
+    pub inspect: InspectData,
+    pub stats: StatsData,
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    pub update_available: bool,
+}
+
+impl FakeContainer {
+    /// Creates a running container with empty stats and no logs
+    pub fn new(id: &str, name: &str) -> Self {
+        FakeContainer {
+            id: id.to_string(),
+            labels: BTreeMap::new(),
+            running: true,
+            inspect: InspectData {
+                id: id.to_string(),
+                container_name: name.to_string(),
+                status: "running".to_string(),
+                restart_policy: "no".to_string(),
+                health: "N/A".to_string(),
+                ..Default::default()
+            },
+            stats: StatsData {
+                container_name: name.to_string(),
+                ..Default::default()
+            },
+            stdout: vec![],
+            stderr: vec![],
+            update_available: false,
+        }
+    }
+
+    /// Places the container in a compose project
+    pub fn stack(self, stack: &str) -> Self {
+        self.label(STACK_LABEL, stack)
+    }
+
+    /// Marks the container as created for a compose service
+    pub fn service(self, service: &str) -> Self {
+        self.label(SERVICE_LABEL, service)
+    }
+
+    /// Adds a label to the container
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Marks the container as stopped
+    pub fn stopped(mut self) -> Self {
+        self.running = false;
+        self.inspect.status = "exited".to_string();
+        self
+    }
+
+    /// Sets the image the container was created from
+    pub fn image(mut self, image: &str) -> Self {
+        self.inspect.image = image.to_string();
+        self
+    }
+
+    /// Appends lines to the container's stdout log
+    pub fn log_lines(mut self, lines: &[&str]) -> Self {
+        self.stdout.extend(lines.iter().map(|l| l.to_string()));
+        self
+    }
+
+    /// Marks the container's image as having a newer version to pull
+    pub fn with_update(mut self) -> Self {
+        self.update_available = true;
+        self
+    }
+
+    fn name(&self) -> &str {
+        &self.inspect.container_name
+    }
+
+    fn matches(&self, container: &str) -> bool {
+        self.id == container || self.name() == container
+    }
+}
+
+/// Side effects recorded by the [`FakeRuntime`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeCall {
+    Logs(String),
+    Restart(String),
+    Pull(String),
+    Remove(Vec<String>),
+    ComposeUp(String),
+}
+
+/// In-memory container runtime for exercising commands without a docker daemon
+#[derive(Debug, Default)]
+pub struct FakeRuntime {
+    containers: Mutex<Vec<FakeContainer>>,
+    calls: Mutex<Vec<FakeCall>>,
+    started: Vec<FakeContainer>,
+}
+
+impl FakeRuntime {
+    pub fn new(containers: Vec<FakeContainer>) -> Self {
+        FakeRuntime {
+            containers: Mutex::new(containers),
+            ..Default::default()
+        }
+    }
+
+    /// Sets containers that start once events are watched, replacing any container
+    /// with the same name as a recreate would
+    pub fn with_started(mut self, containers: Vec<FakeContainer>) -> Self {
+        self.started = containers;
+        self
+    }
+
+    /// Returns every side effect performed so far, in order
+    pub fn calls(&self) -> Vec<FakeCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: FakeCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+
+    fn find(&self, container: &str) -> anyhow::Result<FakeContainer> {
+        self.containers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|c| c.matches(container))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No such container: {container}"))
+    }
+}
+
+/// Joins lines into a reader the way a log stream would emit them
+fn lines_reader(lines: &[String]) -> Box<dyn Read + Send> {
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Box::new(Cursor::new(text.into_bytes()))
+}
+
+impl ContainerRuntime for FakeRuntime {
+    fn list(&self, all: bool) -> anyhow::Result<Vec<ContainerSummary>> {
+        Ok(self
+            .containers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| all || c.running)
+            .map(|c| ContainerSummary {
+                id: c.id.clone(),
+                name: c.name().to_string(),
+                image: c.inspect.image.clone(),
+                state: c.inspect.status.clone(),
+                labels: c.labels.clone(),
+            })
+            .collect())
+    }
+
+    fn inspect(&self, containers: &[String]) -> anyhow::Result<Vec<InspectData>> {
+        // like `docker inspect`, unknown containers are skipped
+        Ok(containers
+            .iter()
+            .filter_map(|c| self.find(c).ok())
+            .map(|c| InspectData {
+                stack: c.labels.get(STACK_LABEL).cloned(),
+                ..c.inspect
+            })
+            .collect())
+    }
+
+    fn stats(&self, containers: &[String]) -> anyhow::Result<Vec<StatsData>> {
+        Ok(containers
+            .iter()
+            .filter_map(|c| self.find(c).ok())
+            .filter(|c| c.running)
+            .map(|c| c.stats)
+            .collect())
+    }
+
+    /// Lines are returned verbatim; time ranges and timestamps are not simulated
+    fn logs(&self, container: &str, options: &LogOptions) -> anyhow::Result<LogStream> {
+        let found = self.find(container)?;
+        self.record(FakeCall::Logs(container.to_string()));
+
+        let tail = options.tail.map_or(usize::MAX, |t| t as usize);
+        let stdout = &found.stdout[found.stdout.len().saturating_sub(tail)..];
+        let stderr = &found.stderr[found.stderr.len().saturating_sub(tail)..];
+
+        Ok(LogStream::from_readers(
+            Some(lines_reader(stdout)),
+            Some(lines_reader(stderr)),
+        ))
+    }
+
+    fn restart(&self, container: &str) -> anyhow::Result<()> {
+        self.find(container)?;
+        self.record(FakeCall::Restart(container.to_string()));
+
+        Ok(())
+    }
+
+    /// Pulls report no progress, and a made-up digest when an update was available
+    fn pull(&self, image: &str, _progress: &mut dyn FnMut(&str)) -> anyhow::Result<PullResult> {
+        self.record(FakeCall::Pull(image.to_string()));
+
+        let mut containers = self.containers.lock().unwrap();
+        let mut is_updated = false;
+        for container in containers.iter_mut().filter(|c| c.inspect.image == image) {
+            is_updated |= container.update_available;
+            container.update_available = false;
+        }
+
+        Ok(PullResult {
+            updated: is_updated,
+            digest: is_updated.then(|| format!("sha256:{image}")),
+        })
+    }
+
+    fn remove(&self, containers: &[String]) -> anyhow::Result<()> {
+        self.record(FakeCall::Remove(containers.to_vec()));
+
+        self.containers
+            .lock()
+            .unwrap()
+            .retain(|c| !containers.iter().any(|r| c.matches(r)));
+
+        Ok(())
+    }
+
+    fn compose_up(&self, compose_file: &str) -> anyhow::Result<()> {
+        self.record(FakeCall::ComposeUp(compose_file.to_string()));
+
+        Ok(())
+    }
+
+    fn events(&self, actions: &[&str]) -> anyhow::Result<EventStream> {
+        if !actions.contains(&"start") {
+            return Ok(EventStream::from_events(vec![]));
+        }
+
+        let mut containers = self.containers.lock().unwrap();
+        let events = self
+            .started
+            .iter()
+            .map(|started| {
+                containers.retain(|c| c.name() != started.name());
+                containers.push(started.clone());
+
+                let mut attributes = started.labels.clone();
+                attributes.insert("name".to_string(), started.name().to_string());
+                attributes.insert("image".to_string(), started.inspect.image.clone());
+
+                ContainerEvent {
+                    action: "start".to_string(),
+                    actor: EventActor {
+                        id: started.id.clone(),
+                        attributes,
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Ok(EventStream::from_events(events))
+    }
+}
@@ -0,0 +1,654 @@
+use crate::models::ContainerSummary;
+use crate::printer::{color_println_fmt, Color};
+use crate::runtime::ContainerRuntime;
+use crate::utils::is_terminal;
+use anyhow::Context;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Which containers a command targets
+///
+/// Every positional pattern, `--stacks` entry and `--label` adds containers to the
+/// selection, `--exclude` removes them again. Patterns may use `*` and `?` globs.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Selector {
+    /// Containers by name, id, glob (e.g. `media-*`) or stack/service (e.g. `media/plex`)
+    pub containers: Vec<String>,
+
+    /// Containers in the specified stacks (compose projects), globs allowed
+    #[arg(short, long)]
+    pub stacks: Vec<String>,
+
+    /// Containers with a label, as `key=value` or just `key`
+    #[arg(short, long)]
+    pub label: Vec<String>,
+
+    /// Leave out containers matching a name, glob or stack/service pattern
+    #[arg(short, long)]
+    pub exclude: Vec<String>,
+
+    /// Select all running containers
+    #[arg(short, long)]
+    pub all: bool,
+
+    /// Let --all, globs, stacks and labels match stopped containers too
+    #[arg(skip)]
+    pub stopped: bool,
+}
+
+/// A requested container or stack that matched nothing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTarget {
+    /// Either "container" or "stack"
+    pub kind: &'static str,
+    pub name: String,
+    /// Closest existing names, best match first
+    pub suggestions: Vec<String>,
+}
+
+/// Error returned when a selection names containers or stacks that don't exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTargets(pub Vec<UnknownTarget>);
+
+impl fmt::Display for UnknownTargets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown containers or stacks:")?;
+        for unknown in &self.0 {
+            write!(f, "\n  {} '{}'", unknown.kind, unknown.name)?;
+            if !unknown.suggestions.is_empty() {
+                write!(f, " (did you mean: {}?)", unknown.suggestions.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownTargets {}
+
+/// A container resolved by a [`Selector`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub id: String,
+    pub name: String,
+    /// Compose project (stack) the container belongs to
+    pub stack: Option<String>,
+}
+
+impl From<ContainerSummary> for Target {
+    fn from(container: ContainerSummary) -> Self {
+        Target {
+            stack: container.stack().map(str::to_string),
+            id: container.id,
+            name: container.name,
+        }
+    }
+}
+
+impl Selector {
+    /// Returns true if nothing was selected
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.containers.is_empty() && self.stacks.is_empty() && self.label.is_empty()
+    }
+
+    /// Makes --all, globs, stacks and labels match stopped containers too
+    pub fn with_stopped(mut self, stopped: bool) -> Self {
+        self.stopped = stopped;
+        self
+    }
+
+    /// Resolves the selection against the runtime's containers
+    ///
+    /// Targets come back deduplicated, in selection order, with canonical names and
+    /// full ids. Globs, stacks and labels only match running containers unless
+    /// [`Selector::with_stopped`] was set, while an exact name or id may also refer to
+    /// a stopped one.
+    ///
+    /// Fails with [`UnknownTargets`] if any requested container or stack matches nothing.
+    pub fn resolve(&self, runtime: &dyn ContainerRuntime) -> anyhow::Result<Vec<Target>> {
+        if self.is_empty() {
+            anyhow::bail!(
+                "Must specify containers, use --stacks (-s), --label (-l) or use --all (-a)"
+            );
+        }
+
+        let listed = runtime.list(true)?;
+        let running = listed.iter().filter(|c| self.stopped || c.is_running());
+
+        let mut selected: Vec<ContainerSummary> = vec![];
+        let mut unknown: Vec<UnknownTarget> = vec![];
+
+        if self.all {
+            selected.extend(running.clone().cloned());
+        }
+
+        for pattern in &self.containers {
+            if is_glob(pattern) || pattern.contains('/') {
+                let matched = running
+                    .clone()
+                    .filter(|c| matches_container(pattern, c))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if matched.is_empty() {
+                    unknown.push(unknown_container(pattern, &listed));
+                }
+                selected.extend(matched);
+            } else if let Some(container) = find_exact(&listed, pattern) {
+                selected.push(container.clone());
+            } else {
+                unknown.push(unknown_container(pattern, &listed));
+            }
+        }
+
+        for stack in &self.stacks {
+            let matched = running
+                .clone()
+                .filter(|c| c.stack().is_some_and(|s| glob_match(stack, s)))
+                .cloned()
+                .collect::<Vec<_>>();
+            // a stack whose containers are all stopped exists, it just has nothing to select
+            let exists = listed
+                .iter()
+                .any(|c| c.stack().is_some_and(|s| glob_match(stack, s)));
+            if !exists {
+                let stacks = listed.iter().filter_map(|c| c.stack());
+                unknown.push(UnknownTarget {
+                    kind: "stack",
+                    name: stack.clone(),
+                    suggestions: suggest(stack, stacks),
+                });
+            }
+            selected.extend(matched);
+        }
+
+        for label in &self.label {
+            selected.extend(running.clone().filter(|c| matches_label(label, c)).cloned());
+        }
+
+        if !unknown.is_empty() {
+            return Err(UnknownTargets(unknown).into());
+        }
+
+        let mut seen = HashSet::new();
+
+        Ok(selected
+            .into_iter()
+            .filter(|c| !self.exclude.iter().any(|e| matches_container(e, c)))
+            .filter(|c| seen.insert(c.id.clone()))
+            .map(Target::from)
+            .collect())
+    }
+
+    /// Returns true if a running container is selected, e.g. one that started after
+    /// the selection was resolved
+    pub fn matches(&self, container: &ContainerSummary) -> bool {
+        let selected = self.all
+            || self
+                .containers
+                .iter()
+                .any(|p| matches_container(p, container))
+            || self
+                .stacks
+                .iter()
+                .any(|s| container.stack().is_some_and(|stack| glob_match(s, stack)))
+            || self.label.iter().any(|l| matches_label(l, container));
+
+        selected && !self.exclude.iter().any(|e| matches_container(e, container))
+    }
+
+    /// Resolves the selection, or lets the user pick containers interactively when
+    /// nothing was selected and both stdin and stdout are terminals
+    pub fn resolve_or_pick(&self, runtime: &dyn ContainerRuntime) -> anyhow::Result<Vec<Target>> {
+        if self.is_empty() && is_terminal() && io::stdin().is_terminal() {
+            return pick(runtime, &mut io::stdin().lock());
+        }
+
+        self.resolve(runtime)
+    }
+}
+
+/// Prompts the user to pick from the running containers by number or name, reading
+/// the answer from `input`
+fn pick(runtime: &dyn ContainerRuntime, input: &mut dyn BufRead) -> anyhow::Result<Vec<Target>> {
+    let mut running = runtime
+        .list(false)?
+        .into_iter()
+        .map(Target::from)
+        .collect::<Vec<_>>();
+    running.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if running.is_empty() {
+        return Ok(vec![]);
+    }
+
+    for (i, target) in running.iter().enumerate() {
+        println!(
+            "{:>3}) {}",
+            i + 1,
+            color_println_fmt(Color::Cyan, &target.name)
+        );
+    }
+    print!("Select containers (e.g. 1 3 5-7, or names): ");
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    let mut picked = vec![];
+    for token in answer
+        .split([' ', ','])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        let range = token
+            .split_once('-')
+            .and_then(|(a, b)| Some((a.parse::<usize>().ok()?, b.parse::<usize>().ok()?)));
+
+        let indices = match (token.parse::<usize>(), range) {
+            (Ok(i), _) => i..=i,
+            (_, Some((a, b))) => a..=b,
+            // anything else is treated as a selection pattern
+            _ => {
+                let selector = Selector {
+                    containers: vec![token.to_string()],
+                    ..Default::default()
+                };
+                picked.extend(selector.resolve(runtime)?);
+                continue;
+            }
+        };
+
+        for i in indices {
+            let target = running
+                .get(i.wrapping_sub(1))
+                .with_context(|| format!("No container numbered {i}"))?;
+            picked.push(target.clone());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    picked.retain(|t| seen.insert(t.id.clone()));
+
+    Ok(picked)
+}
+
+/// Returns the names of the targets
+pub fn target_names(targets: &[Target]) -> Vec<String> {
+    targets.iter().map(|t| t.name.clone()).collect()
+}
+
+/// Finds a container by exact name, full id or id prefix
+fn find_exact<'a>(
+    containers: &'a [ContainerSummary],
+    name_or_id: &str,
+) -> Option<&'a ContainerSummary> {
+    containers
+        .iter()
+        .find(|c| c.name == name_or_id)
+        .or_else(|| containers.iter().find(|c| c.id.starts_with(name_or_id)))
+}
+
+/// Matches a name/id glob, or a `stack/service` pattern against compose labels
+fn matches_container(pattern: &str, container: &ContainerSummary) -> bool {
+    if let Some((stack, service)) = pattern.split_once('/') {
+        return container.stack().is_some_and(|s| glob_match(stack, s))
+            && container.service().is_some_and(|s| glob_match(service, s));
+    }
+
+    glob_match(pattern, &container.name) || (!is_glob(pattern) && container.id.starts_with(pattern))
+}
+
+/// Matches `key=value` against a label value, or just `key` against its presence
+fn matches_label(label: &str, container: &ContainerSummary) -> bool {
+    match label.split_once('=') {
+        Some((key, value)) => container
+            .labels
+            .get(key)
+            .is_some_and(|v| glob_match(value, v)),
+        None => container.labels.contains_key(label),
+    }
+}
+
+/// Builds the unknown entry for a container pattern, suggesting similar names
+fn unknown_container(pattern: &str, containers: &[ContainerSummary]) -> UnknownTarget {
+    UnknownTarget {
+        kind: "container",
+        name: pattern.to_string(),
+        suggestions: suggest(pattern, containers.iter().map(|c| c.name.as_str())),
+    }
+}
+
+/// Picks up to three candidates closest to `name` by edit distance
+///
+/// Candidates containing the name (e.g. `plex` for `media-plex`) are always considered.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut scored = candidates
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let distance = edit_distance(&name, &lower);
+            if distance <= max_distance || lower.contains(&name) {
+                Some((distance, candidate.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    scored.sort();
+
+    scored.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Matches text against a pattern where `*` is any run of characters and `?` any one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it currently absorbs up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, absorbed)) = backtrack {
+            p = star + 1;
+            t = absorbed + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{FakeContainer, FakeRuntime};
+
+    fn runtime() -> FakeRuntime {
+        FakeRuntime::new(vec![
+            FakeContainer::new("a1", "media-plex")
+                .stack("media")
+                .service("plex"),
+            FakeContainer::new("b2", "media-sonarr")
+                .stack("media")
+                .service("sonarr")
+                .label("backup", "nightly"),
+            FakeContainer::new("c3", "proxy")
+                .stack("edge")
+                .service("caddy"),
+            FakeContainer::new("d4", "media-old")
+                .stack("media")
+                .service("old")
+                .stopped(),
+            FakeContainer::new("e5", "scratch")
+                .stack("lab")
+                .label("backup", "weekly")
+                .stopped(),
+            FakeContainer::new("f6", "db").label("backup", "nightly"),
+        ])
+    }
+
+    fn resolve(selector: Selector) -> anyhow::Result<Vec<String>> {
+        Ok(target_names(&selector.resolve(&runtime())?))
+    }
+
+    fn names(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn resolves_stack_service_patterns() {
+        let selector = Selector {
+            containers: names(&["media/sonarr", "*/caddy"]),
+            ..Default::default()
+        };
+
+        assert_eq!(resolve(selector).unwrap(), ["media-sonarr", "proxy"]);
+    }
+
+    #[test]
+    fn globs_match_running_containers_and_literals_any_container() {
+        let selector = Selector {
+            containers: names(&["media-*", "scratch"]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            resolve(selector).unwrap(),
+            ["media-plex", "media-sonarr", "scratch"]
+        );
+    }
+
+    #[test]
+    fn literal_names_are_not_globs() {
+        let selector = Selector {
+            containers: names(&["media"]),
+            ..Default::default()
+        };
+
+        assert!(resolve(selector).is_err());
+    }
+
+    #[test]
+    fn ids_resolve_to_canonical_names() {
+        let targets = Selector {
+            containers: names(&["c3"]),
+            ..Default::default()
+        }
+        .resolve(&runtime())
+        .unwrap();
+
+        assert_eq!(
+            targets,
+            [Target {
+                id: "c3".to_string(),
+                name: "proxy".to_string(),
+                stack: Some("edge".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn labels_filter_by_value_or_presence() {
+        let by_value = Selector {
+            label: names(&["backup=nightly"]),
+            ..Default::default()
+        };
+        let by_key = Selector {
+            label: names(&["backup"]),
+            ..Default::default()
+        };
+
+        assert_eq!(resolve(by_value).unwrap(), ["media-sonarr", "db"]);
+        assert_eq!(
+            resolve(by_key.with_stopped(true)).unwrap(),
+            ["media-sonarr", "scratch", "db"]
+        );
+    }
+
+    #[test]
+    fn exclude_wins_over_includes() {
+        let selector = Selector {
+            containers: names(&["proxy"]),
+            stacks: names(&["media"]),
+            exclude: names(&["media/plex", "proxy"]),
+            ..Default::default()
+        };
+
+        assert_eq!(resolve(selector).unwrap(), ["media-sonarr"]);
+    }
+
+    #[test]
+    fn selections_are_deduplicated_in_order() {
+        let selector = Selector {
+            containers: names(&["db", "media-plex"]),
+            stacks: names(&["med*"]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            resolve(selector).unwrap(),
+            ["db", "media-plex", "media-sonarr"]
+        );
+    }
+
+    #[test]
+    fn a_stack_without_running_containers_selects_nothing() {
+        let selector = Selector {
+            stacks: names(&["lab"]),
+            ..Default::default()
+        };
+
+        assert!(resolve(selector).unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_empty_selection_is_rejected() {
+        let error = resolve(Selector::default()).unwrap_err();
+
+        assert!(error.to_string().starts_with("Must specify containers"));
+    }
+
+    #[test]
+    fn pick_accepts_numbers_ranges_and_names() {
+        // running containers are numbered by name: db, media-plex, media-sonarr, proxy
+        let mut input = "4 1-2,media-sonarr 2\n".as_bytes();
+
+        let picked = pick(&runtime(), &mut input).unwrap();
+
+        assert_eq!(
+            target_names(&picked),
+            ["proxy", "db", "media-plex", "media-sonarr"]
+        );
+    }
+
+    #[test]
+    fn pick_rejects_numbers_out_of_range() {
+        let error = pick(&runtime(), &mut "5\n".as_bytes()).unwrap_err();
+
+        assert_eq!(error.to_string(), "No container numbered 5");
+    }
+
+    #[test]
+    fn glob_match_handles_stars_and_question_marks() {
+        assert!(glob_match("media-*", "media-plex"));
+        assert!(glob_match("*-p?ex", "media-plex"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("media-*", "media"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("plex", "media-plex"));
+    }
+
+    #[test]
+    fn unknown_containers_and_stacks_fail_with_suggestions() {
+        let selector = Selector {
+            containers: names(&["media-plx", "nothing-like-it"]),
+            stacks: names(&["medai"]),
+            ..Default::default()
+        };
+
+        let error = selector.resolve(&runtime()).unwrap_err();
+
+        assert!(error.downcast_ref::<UnknownTargets>().is_some());
+        assert_eq!(
+            error.to_string(),
+            "Unknown containers or stacks:\n  \
+             container 'media-plx' (did you mean: media-plex, media-old?)\n  \
+             container 'nothing-like-it'\n  \
+             stack 'medai' (did you mean: media?)"
+        );
+    }
+
+    #[test]
+    fn unknown_globs_fail_instead_of_selecting_nothing() {
+        let selector = Selector {
+            containers: names(&["web-*"]),
+            ..Default::default()
+        };
+
+        let error = selector.resolve(&runtime()).unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<UnknownTargets>().unwrap().0[0].name,
+            "web-*"
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "plex"), 4);
+        assert_eq!(edit_distance("plex", ""), 4);
+        assert_eq!(edit_distance("plex", "plex"), 0);
+        assert_eq!(edit_distance("plex", "plox"), 1);
+        assert_eq!(edit_distance("plex", "pelx"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+        assert_eq!(edit_distance("日本語", "日本"), 1);
+    }
+
+    #[test]
+    fn suggest_ranks_by_distance_within_the_threshold() {
+        let candidates = ["raddar", "radarr", "sonarr", "radar", "radarr"];
+
+        assert_eq!(
+            suggest("RADARX", candidates.into_iter()),
+            ["radar", "radarr", "raddar"]
+        );
+        assert_eq!(
+            suggest("radarx", candidates.into_iter().chain(["radarxx"])),
+            ["radar", "radarr", "radarxx"]
+        );
+        assert!(suggest("postgres", candidates.into_iter()).is_empty());
+    }
+
+    #[test]
+    fn suggest_keeps_candidates_containing_the_name() {
+        let candidates = [
+            "media-plex",
+            "media-plex-meta-manager",
+            "plexinc",
+            "cache",
+            "plux",
+        ];
+
+        assert_eq!(
+            suggest("PLEX", candidates.into_iter()),
+            ["plux", "plexinc", "media-plex"]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Destinations for log lines besides the terminal
+//!
+//! [`LogSink`]s receive every line that passes the `logs` filters, so the same
+//! selection can be archived or forwarded while it is followed.
+
+mod archive;
+mod loki;
+mod syslog;
+
+pub use archive::{Archive, RotationPolicy};
+pub use loki::{BatchPolicy, Loki, LokiEndpoint};
+pub use syslog::{Syslog, SyslogTarget, DEFAULT_SYSLOG_SOCKET};
+
+use crate::logs::LogLine;
+
+/// A destination for followed log lines
+pub trait LogSink {
+    /// Short description used when reporting failures, e.g. the archive directory
+    fn name(&self) -> String;
+
+    /// Writes a line; failures are reported by the caller, which keeps following
+    fn write(&mut self, line: &LogLine) -> anyhow::Result<()>;
+
+    /// Writes out anything still buffered, called once following ends
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,432 @@
+use super::LogSink;
+use crate::logs::LogLine;
+use anyhow::Context;
+use chrono::{Local, NaiveDateTime, SecondsFormat};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// Timestamp format of rotated segments, e.g. `media-plex.20240101T120000.000.log`
+const SEGMENT_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+
+/// When an archived container log is rotated and how many old segments are kept
+#[derive(Debug, Clone, Default)]
+pub struct RotationPolicy {
+    /// Rotate once the current file reaches this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate once the current file has been written to for this long
+    pub max_age: Option<Duration>,
+    /// Gzip rotated segments
+    pub compress: bool,
+    /// Number of rotated segments kept per container
+    pub retain: usize,
+}
+
+/// The file lines of one container are currently appended to
+struct ArchiveFile {
+    file: File,
+    size: u64,
+    created: SystemTime,
+}
+
+/// Writes each container's lines to `<dir>/<container>.log`, rotating by size or age
+pub struct Archive {
+    dir: PathBuf,
+    policy: RotationPolicy,
+    files: HashMap<Arc<str>, ArchiveFile>,
+    /// Segments being compressed and pruned in the background
+    compressing: Vec<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Archive {
+    /// Creates the archive directory if needed
+    pub fn open(dir: &Path, policy: RotationPolicy) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        Ok(Archive {
+            dir: dir.to_path_buf(),
+            policy,
+            files: HashMap::new(),
+            compressing: vec![],
+        })
+    }
+
+    fn current_path(&self, container: &str) -> PathBuf {
+        self.dir.join(format!("{container}.log"))
+    }
+
+    /// Opens the current file of a container for appending, continuing an existing one
+    fn open_file(&self, container: &str) -> anyhow::Result<ArchiveFile> {
+        let path = self.current_path(container);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let metadata = file.metadata()?;
+
+        Ok(ArchiveFile {
+            size: metadata.len(),
+            created: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            file,
+        })
+    }
+
+    fn needs_rotation(&self, file: &ArchiveFile) -> bool {
+        let too_big = self
+            .policy
+            .max_size
+            .is_some_and(|max| file.size >= max && file.size > 0);
+        let too_old = self.policy.max_age.is_some_and(|max| {
+            file.created
+                .elapsed()
+                .is_ok_and(|age| age >= max && file.size > 0)
+        });
+
+        too_big || too_old
+    }
+
+    /// Moves the current file of a container aside, compresses it if configured and
+    /// removes segments beyond the retention count
+    fn rotate(&mut self, container: &str) -> anyhow::Result<()> {
+        self.files.remove(container);
+
+        let current = self.current_path(container);
+        let segment = self.segment_path(container)?;
+        fs::rename(&current, &segment)
+            .with_context(|| format!("Failed to rotate {}", current.display()))?;
+
+        if self.policy.compress {
+            // compress in the background so following is not held up by large segments
+            let dir = self.dir.clone();
+            let container = container.to_string();
+            let retain = self.policy.retain;
+            self.compressing.push(std::thread::spawn(move || {
+                compress(&segment)
+                    .with_context(|| format!("Failed to compress {}", segment.display()))?;
+                prune(&dir, &container, retain)
+            }));
+            Ok(())
+        } else {
+            prune(&self.dir, container, self.policy.retain)
+        }
+    }
+
+    /// Path for the next rotated segment of a container, with a counter suffix when
+    /// segments of the same millisecond exist so segments still sort by age
+    fn segment_path(&self, container: &str) -> anyhow::Result<PathBuf> {
+        let stamp = Local::now().format(SEGMENT_TIME_FORMAT).to_string();
+
+        let last = segments(&self.dir, container)?
+            .into_iter()
+            .filter(|segment| segment.stamp.split('-').next() == Some(stamp.as_str()))
+            .map(|segment| segment.counter)
+            .max();
+
+        let name = match last {
+            Some(counter) => format!("{container}.{stamp}-{}.log", counter + 1),
+            None => format!("{container}.{stamp}.log"),
+        };
+
+        Ok(self.dir.join(name))
+    }
+
+    /// Collects background compressions, waiting for all of them when `wait` is set,
+    /// and returns the first failure
+    fn join_compressions(&mut self, wait: bool) -> anyhow::Result<()> {
+        let (done, running) = std::mem::take(&mut self.compressing)
+            .into_iter()
+            .partition::<Vec<_>, _>(|handle| wait || handle.is_finished());
+        self.compressing = running;
+
+        let mut result = Ok(());
+        for handle in done {
+            let outcome = handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("Compression thread panicked")));
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        // segments must not be left half-compressed when the process exits
+        let _ = self.join_compressions(true);
+    }
+}
+
+impl LogSink for Archive {
+    fn name(&self) -> String {
+        format!("archive {}", self.dir.display())
+    }
+
+    fn write(&mut self, line: &LogLine) -> anyhow::Result<()> {
+        self.join_compressions(false)?;
+
+        let container = &line.container;
+
+        if self
+            .files
+            .get(container)
+            .is_some_and(|f| self.needs_rotation(f))
+        {
+            self.rotate(container)?;
+        }
+
+        if !self.files.contains_key(container) {
+            let file = self.open_file(container)?;
+            self.files.insert(Arc::clone(container), file);
+        }
+
+        let timestamp = line
+            .timestamp
+            .unwrap_or_else(|| Local::now().fixed_offset())
+            .to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let text = format!("{timestamp} {}\n", line.text);
+
+        if let Some(file) = self.files.get_mut(container) {
+            file.file.write_all(text.as_bytes())?;
+            file.size += text.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        for file in self.files.values_mut() {
+            file.file.flush()?;
+        }
+
+        self.join_compressions(true)
+    }
+}
+
+/// Gzips a rotated segment to `<segment>.gz` and removes the original
+fn compress(segment: &Path) -> io::Result<()> {
+    let mut gz_name = segment.as_os_str().to_os_string();
+    gz_name.push(".gz");
+
+    let mut input = File::open(segment)?;
+    let mut encoder = GzEncoder::new(File::create(&gz_name)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+
+    fs::remove_file(segment)
+}
+
+/// A rotated segment, ordered by age
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Segment {
+    time: NaiveDateTime,
+    /// Suffix telling apart segments rotated within the same millisecond
+    counter: u32,
+    /// Part of the file name between the container and the extension
+    stamp: String,
+}
+
+/// Lists the rotated segments of a container, counting a segment still being
+/// compressed once
+fn segments(dir: &Path, container: &str) -> anyhow::Result<BTreeSet<Segment>> {
+    let segments = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stamp = name
+                .strip_prefix(container)?
+                .strip_prefix('.')?
+                .trim_end_matches(".gz")
+                .strip_suffix(".log")?;
+            let (time, counter) = match stamp.split_once('-') {
+                Some((time, counter)) => (time, counter.parse().ok()?),
+                None => (stamp, 0),
+            };
+            Some(Segment {
+                time: NaiveDateTime::parse_from_str(time, SEGMENT_TIME_FORMAT).ok()?,
+                counter,
+                stamp: stamp.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+/// Removes the oldest rotated segments of a container beyond `retain`
+fn prune(dir: &Path, container: &str, retain: usize) -> anyhow::Result<()> {
+    let segments = segments(dir, container)?;
+
+    let excess = segments.len().saturating_sub(retain);
+    for Segment { stamp, .. } in segments.into_iter().take(excess) {
+        let segment = dir.join(format!("{container}.{stamp}.log"));
+        for path in [segment.with_extension("log.gz"), segment] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::Output;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// A fresh directory in the temp dir, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("dsd-util-archive-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestDir(dir)
+        }
+
+        /// File names of the rotated segments, oldest first
+        fn segments(&self) -> Vec<String> {
+            segments(&self.0, "web")
+                .unwrap()
+                .into_iter()
+                .map(|segment| {
+                    let name = format!("web.{}.log", segment.stamp);
+                    if self.0.join(&name).exists() {
+                        name
+                    } else {
+                        format!("{name}.gz")
+                    }
+                })
+                .collect()
+        }
+
+        fn read(&self, name: &str) -> String {
+            let mut text = String::new();
+            let file = File::open(self.0.join(name)).unwrap();
+            if name.ends_with(".gz") {
+                GzDecoder::new(file).read_to_string(&mut text).unwrap();
+            } else {
+                io::BufReader::new(file).read_to_string(&mut text).unwrap();
+            }
+            text
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_lines(archive: &mut Archive, lines: &[&str]) {
+        for text in lines {
+            let raw = format!("2024-01-01T12:00:00Z {text}");
+            archive
+                .write(&LogLine::parse(Arc::from("web"), Output::Stdout, raw))
+                .unwrap();
+        }
+        archive.flush().unwrap();
+    }
+
+    fn policy(retain: usize, compress: bool) -> RotationPolicy {
+        RotationPolicy {
+            max_size: Some(10),
+            max_age: None,
+            compress,
+            retain,
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_into_uniquely_named_segments() {
+        let dir = TestDir::new("size");
+        let mut archive = Archive::open(&dir.0, policy(5, false)).unwrap();
+
+        write_lines(&mut archive, &["one", "two", "three"]);
+
+        let segments = dir.segments();
+        assert_eq!(segments.len(), 2);
+        assert!(segments
+            .iter()
+            .all(|s| s.starts_with("web.2") && s.ends_with(".log")));
+        assert_eq!(
+            dir.read(&segments[0]),
+            "2024-01-01T12:00:00.000000000Z one\n"
+        );
+        assert_eq!(
+            dir.read(&segments[1]),
+            "2024-01-01T12:00:00.000000000Z two\n"
+        );
+        assert_eq!(
+            dir.read("web.log"),
+            "2024-01-01T12:00:00.000000000Z three\n"
+        );
+    }
+
+    #[test]
+    fn prunes_segments_beyond_the_retention_count() {
+        let dir = TestDir::new("retain");
+        let mut archive = Archive::open(&dir.0, policy(1, false)).unwrap();
+
+        write_lines(&mut archive, &["one", "two", "three", "four"]);
+
+        // rotations within a millisecond must not reuse the name of a pruned segment
+        let segments = dir.segments();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(
+            dir.read(&segments[0]),
+            "2024-01-01T12:00:00.000000000Z three\n"
+        );
+    }
+
+    #[test]
+    fn compresses_segments_before_flush_returns() {
+        let dir = TestDir::new("compress");
+        let mut archive = Archive::open(&dir.0, policy(5, true)).unwrap();
+
+        write_lines(&mut archive, &["one", "two", "three"]);
+
+        let segments = dir.segments();
+        assert_eq!(segments.len(), 2);
+        assert!(segments.iter().all(|s| s.ends_with(".log.gz")));
+        let text = segments.iter().map(|s| dir.read(s)).collect::<String>();
+        assert_eq!(
+            text,
+            "2024-01-01T12:00:00.000000000Z one\n2024-01-01T12:00:00.000000000Z two\n"
+        );
+    }
+
+    #[test]
+    fn reports_failed_compressions() {
+        let dir = TestDir::new("compress-error");
+        let mut archive = Archive::open(&dir.0, policy(5, true)).unwrap();
+
+        archive.compressing.push(std::thread::spawn(|| {
+            compress(Path::new("/nonexistent/web.log")).context("Failed to compress")
+        }));
+
+        assert_eq!(
+            archive.flush().unwrap_err().to_string(),
+            "Failed to compress"
+        );
+        assert!(archive.compressing.is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+use super::LogSink;
+use crate::http;
+use crate::logs::{LogLine, Output};
+use anyhow::Context;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Path pushed to when the URL has none
+const PUSH_PATH: &str = "/loki/api/v1/push";
+
+/// Timeout for connecting to and exchanging a push with the endpoint
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First delay before retrying a failed push, doubled on each further failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long the push thread waits for lines while nothing is buffered
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Attempts made to deliver what is still buffered once following ends
+const FINAL_ATTEMPTS: usize = 3;
+
+/// How lines are batched before being pushed
+#[derive(Debug, Clone)]
+pub struct BatchPolicy {
+    /// Push once this many lines are buffered
+    pub max_lines: usize,
+    /// Push lines at the latest this long after the first of them was buffered
+    pub max_wait: Duration,
+    /// Lines kept for retrying while the endpoint fails, the oldest are dropped beyond it
+    pub max_pending: usize,
+}
+
+/// A Loki push URL split into its parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LokiEndpoint {
+    /// `host:port` to connect to
+    pub address: String,
+    pub host: String,
+    pub path: String,
+}
+
+impl LokiEndpoint {
+    /// Parses `http://host[:port][/path]`, pushing to the standard path when none is given
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        if url.starts_with("https://") {
+            anyhow::bail!(
+                "https is not supported for {url}, use a plain http endpoint or a local proxy"
+            );
+        }
+        let rest = url.strip_prefix("http://").with_context(|| {
+            format!("Invalid Loki URL: {url}, expected e.g. http://localhost:3100")
+        })?;
+
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            anyhow::bail!("Invalid Loki URL: {url}, missing host");
+        }
+
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+        let path = if path.is_empty() || path == "/" {
+            PUSH_PATH.to_string()
+        } else {
+            path.to_string()
+        };
+
+        Ok(LokiEndpoint {
+            address,
+            host: host.to_string(),
+            path,
+        })
+    }
+
+    /// Pushes a batch of entries as one request
+    fn push(&self, entries: &[Entry]) -> anyhow::Result<()> {
+        let body = push_body(entries).to_string();
+
+        let address = std::net::ToSocketAddrs::to_socket_addrs(&self.address)
+            .with_context(|| format!("Failed to resolve {}", self.address))?
+            .next()
+            .with_context(|| format!("Failed to resolve {}", self.address))?;
+        let stream = TcpStream::connect_timeout(&address, PUSH_TIMEOUT)
+            .with_context(|| format!("Failed to connect to {}", self.address))?;
+        stream.set_read_timeout(Some(PUSH_TIMEOUT))?;
+        stream.set_write_timeout(Some(PUSH_TIMEOUT))?;
+
+        let response = http::send(
+            stream,
+            "POST",
+            &self.host,
+            &self.path,
+            &[("Content-Type", "application/json")],
+            Some(body.as_bytes()),
+        )?;
+
+        if !response.is_success() {
+            let status = response.status;
+            let message = response.text().unwrap_or_default();
+            anyhow::bail!("Loki responded with {status}: {}", message.trim());
+        }
+
+        Ok(())
+    }
+}
+
+/// A line waiting to be pushed, with the labels of the stream it belongs to
+struct Entry {
+    labels: BTreeMap<&'static str, String>,
+    /// Unix time in nanoseconds
+    timestamp: String,
+    text: String,
+}
+
+impl Entry {
+    fn new(line: &LogLine) -> Self {
+        let mut labels = BTreeMap::new();
+        labels.insert("container", line.container.to_string());
+        if let Some(stack) = &line.stack {
+            labels.insert("stack", stack.to_string());
+        }
+        let stream = match line.output {
+            Output::Stdout => "stdout",
+            Output::Stderr => "stderr",
+            Output::Marker => "marker",
+        };
+        labels.insert("stream", stream.to_string());
+
+        let timestamp = line
+            .timestamp
+            .map_or_else(Utc::now, |ts| ts.with_timezone(&Utc))
+            .timestamp_nanos_opt()
+            .unwrap_or_default();
+
+        Entry {
+            labels,
+            timestamp: timestamp.to_string(),
+            text: line.text.clone(),
+        }
+    }
+}
+
+/// Builds the JSON push body, grouping entries into one stream per label set
+fn push_body(entries: &[Entry]) -> Value {
+    let mut streams: BTreeMap<&BTreeMap<&str, String>, Vec<[&str; 2]>> = BTreeMap::new();
+    for entry in entries {
+        streams
+            .entry(&entry.labels)
+            .or_default()
+            .push([&entry.timestamp, &entry.text]);
+    }
+
+    let streams = streams
+        .into_iter()
+        .map(|(labels, values)| json!({ "stream": labels, "values": values }))
+        .collect::<Vec<_>>();
+
+    json!({ "streams": streams })
+}
+
+/// Pushes lines to a Loki-compatible endpoint with container, stack and stream labels
+///
+/// Lines are batched and pushed from a background thread, so a slow or unreachable
+/// endpoint does not hold up following. Failed pushes are retried with backoff.
+pub struct Loki {
+    endpoint: LokiEndpoint,
+    tx: Option<Sender<Entry>>,
+    worker: Option<JoinHandle<()>>,
+    /// Why the last push failed, cleared once a push succeeds again
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Loki {
+    /// Starts the thread pushing batches to `endpoint`
+    pub fn start(endpoint: LokiEndpoint, policy: BatchPolicy) -> Self {
+        let (tx, rx) = std::sync::mpsc::channel::<Entry>();
+        let error = Arc::new(Mutex::new(None));
+
+        let batcher = Batcher {
+            endpoint: endpoint.clone(),
+            policy,
+            pending: vec![],
+            error: Arc::clone(&error),
+        };
+        let worker = std::thread::spawn(move || batcher.run(rx));
+
+        Loki {
+            endpoint,
+            tx: Some(tx),
+            worker: Some(worker),
+            error,
+        }
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.error.lock().ok().and_then(|e| e.clone())
+    }
+}
+
+/// Buffers entries on the push thread until they are delivered
+struct Batcher {
+    endpoint: LokiEndpoint,
+    policy: BatchPolicy,
+    pending: Vec<Entry>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Batcher {
+    /// Receives entries until the sink is flushed, pushing a batch once it is full or
+    /// has waited long enough, then makes a last attempt to deliver what is left
+    fn run(mut self, rx: Receiver<Entry>) {
+        let mut next_push: Option<Instant> = None;
+        let mut retry_delay: Option<Duration> = None;
+
+        loop {
+            let timeout = next_push.map_or(IDLE_TIMEOUT, |at| {
+                at.saturating_duration_since(Instant::now())
+            });
+
+            match rx.recv_timeout(timeout) {
+                Ok(entry) => {
+                    self.pending.push(entry);
+                    let full = self.pending.len() >= self.policy.max_lines;
+                    next_push = match next_push {
+                        // while backing off, a full batch waits for the retry
+                        _ if full && retry_delay.is_none() => Some(Instant::now()),
+                        Some(at) => Some(at),
+                        None => Some(Instant::now() + self.policy.max_wait),
+                    };
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if next_push.is_some_and(|at| at <= Instant::now()) {
+                if self.push() {
+                    next_push = None;
+                    retry_delay = None;
+                } else {
+                    let delay = retry_delay.map_or(RETRY_DELAY, |d| (d * 2).min(MAX_RETRY_DELAY));
+                    retry_delay = Some(delay);
+                    next_push = Some(Instant::now() + delay);
+                }
+            }
+        }
+
+        for attempt in 0..FINAL_ATTEMPTS {
+            if self.pending.is_empty() || self.push() {
+                return;
+            }
+            if attempt + 1 < FINAL_ATTEMPTS {
+                std::thread::sleep(RETRY_DELAY);
+            }
+        }
+
+        if let Ok(mut error) = self.error.lock() {
+            let cause = error.take().unwrap_or_default();
+            *error = Some(format!(
+                "{} line(s) not delivered: {cause}",
+                self.pending.len()
+            ));
+        }
+    }
+
+    /// Pushes everything buffered, returning whether it was delivered
+    fn push(&mut self) -> bool {
+        match self.endpoint.push(&self.pending) {
+            Ok(()) => {
+                self.pending.clear();
+                if let Ok(mut error) = self.error.lock() {
+                    *error = None;
+                }
+                true
+            }
+            Err(e) => {
+                let excess = self.pending.len().saturating_sub(self.policy.max_pending);
+                self.pending.drain(..excess);
+                if let Ok(mut error) = self.error.lock() {
+                    *error = Some(format!("{e:#}"));
+                }
+                false
+            }
+        }
+    }
+}
+
+impl LogSink for Loki {
+    fn name(&self) -> String {
+        format!("loki http://{}{}", self.endpoint.host, self.endpoint.path)
+    }
+
+    fn write(&mut self, line: &LogLine) -> anyhow::Result<()> {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(Entry::new(line));
+        }
+
+        // lines stay buffered for retrying, but the caller should know pushes fail
+        match self.last_error() {
+            Some(error) => anyhow::bail!("{error}, retrying"),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.tx = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+
+        match self.last_error() {
+            Some(error) => anyhow::bail!("{error}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers one push per response on a local port, yielding the pushed bodies
+    fn serve(responses: &[&'static str]) -> (LokiEndpoint, JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = responses.to_vec();
+
+        let server = std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+
+                    let mut head = String::new();
+                    while reader.read_line(&mut head).unwrap() > 2 {}
+                    assert!(head.starts_with("POST /loki/api/v1/push HTTP/1.1\r\n"));
+
+                    let length = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .map_or(0, |l| l.parse().unwrap());
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+                    serde_json::from_slice(&body).unwrap()
+                })
+                .collect()
+        });
+
+        (LokiEndpoint::parse(&url).unwrap(), server)
+    }
+
+    fn policy(max_lines: usize) -> BatchPolicy {
+        BatchPolicy {
+            max_lines,
+            max_wait: Duration::from_secs(60),
+            max_pending: 100,
+        }
+    }
+
+    fn line(container: &str, output: Output, raw: &str) -> LogLine {
+        LogLine {
+            stack: Some(Arc::from("media")),
+            ..LogLine::parse(Arc::from(container), output, raw.to_string())
+        }
+    }
+
+    /// Texts pushed per stream, keyed by the stream's container and output labels
+    fn pushed(body: &Value) -> Vec<(String, Vec<String>)> {
+        body["streams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stream| {
+                let labels = &stream["stream"];
+                assert_eq!(labels["stack"], "media");
+                let key = format!("{}/{}", labels["container"], labels["stream"]).replace('"', "");
+                let texts = stream["values"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|v| v[1].as_str().unwrap().to_string())
+                    .collect();
+                (key, texts)
+            })
+            .collect()
+    }
+
+    const NO_CONTENT: &str = "HTTP/1.1 204 No Content\r\n\r\n";
+
+    #[test]
+    fn parse_fills_in_the_port_and_push_path() {
+        assert_eq!(
+            LokiEndpoint::parse("http://localhost:3100").unwrap(),
+            LokiEndpoint {
+                address: "localhost:3100".to_string(),
+                host: "localhost:3100".to_string(),
+                path: PUSH_PATH.to_string(),
+            }
+        );
+        assert_eq!(
+            LokiEndpoint::parse("http://logs/api/push").unwrap(),
+            LokiEndpoint {
+                address: "logs:80".to_string(),
+                host: "logs".to_string(),
+                path: "/api/push".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_urls() {
+        for url in ["https://logs", "logs:3100", "http://", "http:///push"] {
+            assert!(LokiEndpoint::parse(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn pushes_full_batches_and_the_rest_on_flush() {
+        let (endpoint, server) = serve(&[NO_CONTENT, NO_CONTENT]);
+        let mut loki = Loki::start(endpoint, policy(2));
+
+        loki.write(&line("web", Output::Stdout, "2024-01-01T00:00:00Z a"))
+            .unwrap();
+        loki.write(&line("web", Output::Stderr, "2024-01-01T00:00:01Z b"))
+            .unwrap();
+        loki.write(&line("db", Output::Stdout, "c")).unwrap();
+        loki.flush().unwrap();
+
+        let bodies = server.join().unwrap();
+        assert_eq!(
+            pushed(&bodies[0]),
+            [
+                ("web/stderr".to_string(), vec!["b".to_string()]),
+                ("web/stdout".to_string(), vec!["a".to_string()]),
+            ]
+        );
+        assert_eq!(
+            bodies[0]["streams"][1]["values"][0][0],
+            "1704067200000000000"
+        );
+        assert_eq!(
+            pushed(&bodies[1]),
+            [("db/stdout".to_string(), vec!["c".to_string()])]
+        );
+    }
+
+    #[test]
+    fn retries_a_failed_push_with_the_same_lines() {
+        let (endpoint, server) = serve(&[
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 8\r\n\r\nbusy now",
+            NO_CONTENT,
+        ]);
+        let mut loki = Loki::start(endpoint, policy(2));
+
+        loki.write(&line("web", Output::Stdout, "a")).unwrap();
+        loki.write(&line("web", Output::Stdout, "b")).unwrap();
+        loki.flush().unwrap();
+
+        let bodies = server.join().unwrap();
+        let expected = [(
+            "web/stdout".to_string(),
+            vec!["a".to_string(), "b".to_string()],
+        )];
+        assert_eq!(pushed(&bodies[0]), expected);
+        assert_eq!(pushed(&bodies[1]), expected);
+    }
+}
@@ -0,0 +1,272 @@
+use super::LogSink;
+use crate::logs::{LogLine, Output};
+use anyhow::Context;
+use chrono::{Local, SecondsFormat};
+use std::fs;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+/// Socket local syslog daemons listen on
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Facility `user-level messages`
+const FACILITY_USER: u8 = 1;
+
+/// Where syslog messages are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    /// A unix datagram socket such as `/dev/log`
+    Unix(PathBuf),
+    /// A `host:port` address, sent over UDP
+    Udp(String),
+}
+
+impl SyslogTarget {
+    /// Parses `udp://host:port`, `unix:///path` or a plain socket path
+    pub fn parse(target: &str) -> anyhow::Result<Self> {
+        if let Some(address) = target.strip_prefix("udp://") {
+            let address = if address.contains(':') {
+                address.to_string()
+            } else {
+                format!("{address}:514")
+            };
+            return Ok(SyslogTarget::Udp(address));
+        }
+
+        let path = target.strip_prefix("unix://").unwrap_or(target);
+        if path.is_empty() || path.contains("://") {
+            anyhow::bail!(
+                "Invalid syslog target: {target}, expected e.g. /dev/log or udp://host:514"
+            );
+        }
+
+        Ok(SyslogTarget::Unix(PathBuf::from(path)))
+    }
+}
+
+enum Socket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn connect(target: &SyslogTarget) -> anyhow::Result<Self> {
+        match target {
+            SyslogTarget::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(path)
+                    .with_context(|| format!("Failed to connect to {}", path.display()))?;
+                Ok(Socket::Unix(socket))
+            }
+            SyslogTarget::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket
+                    .connect(address)
+                    .with_context(|| format!("Failed to connect to {address}"))?;
+                Ok(Socket::Udp(socket))
+            }
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Unix(socket) => socket.send(message),
+            Socket::Udp(socket) => socket.send(message),
+        }
+    }
+}
+
+/// Sends every line as an RFC 5424 message, with the container as app name and the
+/// container and stack at the start of the message
+pub struct Syslog {
+    target: SyslogTarget,
+    hostname: String,
+    socket: Option<Socket>,
+}
+
+impl Syslog {
+    /// Connects to the syslog socket, failing early when nothing listens on it
+    pub fn connect(target: SyslogTarget) -> anyhow::Result<Self> {
+        let socket = Socket::connect(&target)?;
+
+        Ok(Syslog {
+            target,
+            hostname: hostname(),
+            socket: Some(socket),
+        })
+    }
+
+    /// Formats a line as `<PRI>1 TIMESTAMP HOST APP - - - container=NAME stack=STACK MSG`
+    ///
+    /// Structured data would need an IANA enterprise number, so the container and
+    /// stack are part of the message instead.
+    fn format(&self, line: &LogLine) -> String {
+        let severity = match line.output {
+            Output::Stdout => 6, // informational
+            Output::Stderr => 3, // error
+            Output::Marker => 5, // notice
+        };
+        let timestamp = line
+            .timestamp
+            .unwrap_or_else(|| Local::now().fixed_offset())
+            .to_rfc3339_opts(SecondsFormat::Micros, false);
+
+        let mut origin = format!("container={}", line.container);
+        if let Some(stack) = &line.stack {
+            origin.push_str(&format!(" stack={stack}"));
+        }
+
+        format!(
+            "<{}>1 {timestamp} {} {} - - - {origin} {}",
+            FACILITY_USER * 8 + severity,
+            self.hostname,
+            header_field(&line.container, 48),
+            line.text
+        )
+    }
+}
+
+impl LogSink for Syslog {
+    fn name(&self) -> String {
+        match &self.target {
+            SyslogTarget::Unix(path) => format!("syslog {}", path.display()),
+            SyslogTarget::Udp(address) => format!("syslog udp://{address}"),
+        }
+    }
+
+    fn write(&mut self, line: &LogLine) -> anyhow::Result<()> {
+        let message = self.format(line);
+
+        if let Some(socket) = &self.socket
+            && socket.send(message.as_bytes()).is_ok()
+        {
+            return Ok(());
+        }
+
+        // the daemon may have restarted, reconnect once before giving up on the line
+        self.socket = None;
+        let socket = Socket::connect(&self.target)?;
+        let sent = socket.send(message.as_bytes());
+        self.socket = Some(socket);
+        sent.context("Failed to send syslog message")?;
+
+        Ok(())
+    }
+}
+
+/// Restricts a header field to printable ASCII of at most `max` characters
+fn header_field(value: &str, max: usize) -> String {
+    let field = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect::<String>();
+
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Name of this host, or the nil value `-` when it cannot be read
+fn hostname() -> String {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default();
+
+    header_field(name.trim(), 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn line(output: Output, stack: Option<&str>, raw: &str) -> LogLine {
+        LogLine {
+            stack: stack.map(Arc::from),
+            ..LogLine::parse(Arc::from("web"), output, raw.to_string())
+        }
+    }
+
+    #[test]
+    fn parse_accepts_paths_and_udp_addresses() {
+        assert_eq!(
+            SyslogTarget::parse("/dev/log").unwrap(),
+            SyslogTarget::Unix(PathBuf::from("/dev/log"))
+        );
+        assert_eq!(
+            SyslogTarget::parse("unix:///run/systemd/journal/syslog").unwrap(),
+            SyslogTarget::Unix(PathBuf::from("/run/systemd/journal/syslog"))
+        );
+        assert_eq!(
+            SyslogTarget::parse("udp://logs").unwrap(),
+            SyslogTarget::Udp("logs:514".to_string())
+        );
+        assert_eq!(
+            SyslogTarget::parse("udp://10.0.0.2:1514").unwrap(),
+            SyslogTarget::Udp("10.0.0.2:1514".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_rejects_other_schemes() {
+        for target in ["", "unix://", "tcp://logs:514"] {
+            assert!(SyslogTarget::parse(target).is_err(), "{target:?}");
+        }
+    }
+
+    #[test]
+    fn write_sends_rfc5424_messages_to_the_socket() {
+        let path =
+            std::env::temp_dir().join(format!("dsd-util-syslog-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        let mut syslog = Syslog::connect(SyslogTarget::Unix(path.clone())).unwrap();
+        syslog
+            .write(&line(
+                Output::Stderr,
+                Some("media"),
+                "2024-01-01T00:00:00Z boom",
+            ))
+            .unwrap();
+        syslog
+            .write(&line(Output::Stdout, None, "2024-01-01T00:00:01+02:00 ok"))
+            .unwrap();
+
+        let mut buf = [0; 1024];
+        let mut received = vec![];
+        for _ in 0..2 {
+            let n = listener.recv(&mut buf).unwrap();
+            received.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+        }
+        let _ = fs::remove_file(&path);
+
+        let host = hostname();
+        assert_eq!(
+            received,
+            [
+                format!(
+                    "<11>1 2024-01-01T00:00:00.000000+00:00 {host} web - - - \
+                     container=web stack=media boom"
+                ),
+                format!(
+                    "<14>1 2024-01-01T00:00:01.000000+02:00 {host} web - - - \
+                     container=web ok"
+                ),
+            ]
+        );
+        assert_eq!(syslog.name(), format!("syslog {}", path.display()));
+    }
+
+    #[test]
+    fn header_field_keeps_printable_ascii() {
+        assert_eq!(header_field("my app\u{e9}", 48), "myapp");
+        assert_eq!(header_field("abcdef", 3), "abc");
+        assert_eq!(header_field(" ", 48), "-");
+    }
+}
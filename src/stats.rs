@@ -0,0 +1,811 @@
+use crate::printer::{color_println_fmt, highlight_fmt, terminal_width, Color, Table};
+use crate::runtime::ContainerRuntime;
+use crate::utils::{
+    format_elapsed, format_size, missing_containers, parse_std_duration, StatsData,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DEFAULT_WATCH_INTERVAL: &str = "2s";
+
+const DEFAULT_COLUMNS: &str = "name,status,restart,restarts,health,uptime,cpu,memperc,ports";
+
+/// Options for the `stats` command
+#[derive(Debug, Clone, clap::Args)]
+pub struct StatsArgs {
+    /// Keep refreshing the table in place until Ctrl-C is pressed
+    #[arg(short, long)]
+    pub watch: bool,
+
+    /// Time between refreshes with --watch (e.g. 500ms, 5s)
+    #[arg(long, value_name = "DURATION", default_value = DEFAULT_WATCH_INTERVAL, value_parser = parse_std_duration, requires = "watch")]
+    pub interval: Duration,
+
+    /// Sort rows by a column instead of by stack and name
+    #[arg(long, value_enum, value_name = "COLUMN")]
+    pub sort: Option<StatsSort>,
+
+    /// Reverse the order of rows
+    #[arg(short, long)]
+    pub reverse: bool,
+
+    /// Columns to show, in order
+    #[arg(long, value_enum, value_name = "COLUMNS", value_delimiter = ',', default_value = DEFAULT_COLUMNS)]
+    pub columns: Vec<StatsColumn>,
+
+    /// Draw borders around the table and between columns
+    #[arg(long)]
+    pub border: bool,
+
+    /// Print rows under a header per group, followed by the group's totals (text
+    /// output only)
+    #[arg(long, value_enum, value_name = "GROUP")]
+    pub group_by: Option<GroupBy>,
+
+    /// Only show containers that are unhealthy, restarting, were OOM-killed or were
+    /// restarted more than --max-restarts times
+    #[arg(long)]
+    pub problems: bool,
+
+    /// Include stopped and exited containers, showing how long ago they stopped
+    #[arg(long)]
+    pub include_stopped: bool,
+
+    /// Restarts a container may have before --problems lists it
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "problems")]
+    pub max_restarts: u64,
+}
+
+/// Columns of the stats table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsColumn {
+    Name,
+    /// Compose project the container belongs to
+    Stack,
+    Status,
+    /// Restart policy
+    Restart,
+    /// Times the container was restarted by its restart policy
+    Restarts,
+    /// Exit code of the last run
+    Exitcode,
+    /// Whether the last run was killed for running out of memory
+    Oom,
+    /// How long ago the container last exited
+    Finished,
+    Health,
+    Uptime,
+    /// CPU usage in percent
+    Cpu,
+    /// Memory usage in percent of the limit
+    Memperc,
+    /// Memory used
+    Mem,
+    /// Memory limit, the host's memory when the container has none
+    Memlimit,
+    /// Bytes received / sent over the network
+    Netio,
+    /// Bytes read / written on block devices
+    Blockio,
+    /// Number of processes and threads
+    Pids,
+    Image,
+    /// Published ports
+    Ports,
+}
+
+impl StatsColumn {
+    fn header(self) -> &'static str {
+        match self {
+            StatsColumn::Name => "NAME",
+            StatsColumn::Stack => "STACK",
+            StatsColumn::Status => "STATUS",
+            StatsColumn::Restart => "RESTART",
+            StatsColumn::Restarts => "RESTARTS",
+            StatsColumn::Exitcode => "EXIT",
+            StatsColumn::Oom => "OOM",
+            StatsColumn::Finished => "FINISHED",
+            StatsColumn::Health => "HEALTH",
+            StatsColumn::Uptime => "UPTIME",
+            StatsColumn::Cpu => "CPU %",
+            StatsColumn::Memperc => "MEM %",
+            StatsColumn::Mem => "MEM",
+            StatsColumn::Memlimit => "MEM LIMIT",
+            StatsColumn::Netio => "NET I/O",
+            StatsColumn::Blockio => "BLOCK I/O",
+            StatsColumn::Pids => "PIDS",
+            StatsColumn::Image => "IMAGE",
+            StatsColumn::Ports => "PORTS",
+        }
+    }
+}
+
+/// How rows of the stats table can be grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// Compose project, from the `com.docker.compose.project` label
+    Stack,
+}
+
+/// Columns the stats table can be sorted by
+///
+/// Numbers sort largest first and states sort problems first, ties are broken by
+/// stack and name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsSort {
+    /// Highest CPU usage first
+    Cpu,
+    /// Highest memory usage first
+    Mem,
+    /// Alphabetically by container name
+    Name,
+    /// Longest running first
+    Uptime,
+    /// Restarting, exited and paused before running
+    Status,
+    /// Unhealthy and starting before healthy
+    Health,
+}
+
+/// Container stats to be gathered, one record per container in structured output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerStats {
+    pub name: String,
+    /// Compose project (stack) the container belongs to
+    pub stack: Option<String>,
+    pub status: String,
+    pub health: String,
+    pub restart_policy: String,
+    pub uptime: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub restart_count: u64,
+    pub exit_code: i64,
+    pub oom_killed: bool,
+    /// CPU usage in percent, None when the runtime reported no value
+    pub cpu_usage: Option<f64>,
+    /// Memory usage in percent of the limit, None when the runtime reported no value
+    pub memory_usage: Option<f64>,
+    /// Memory used in bytes, None when the runtime reported no value
+    pub memory_bytes: Option<u64>,
+    pub memory_limit: Option<u64>,
+    pub net_rx: Option<u64>,
+    pub net_tx: Option<u64>,
+    pub block_read: Option<u64>,
+    pub block_write: Option<u64>,
+    pub pids: Option<u64>,
+    pub image: String,
+    pub ports: String,
+}
+
+/// Inspects and samples the given containers, in the order they were inspected
+///
+/// Containers that disappeared or reported no stats are described in the returned
+/// warnings rather than failing the whole table.
+pub fn collect_stats(
+    runtime: &dyn ContainerRuntime,
+    containers: &[String],
+) -> anyhow::Result<(Vec<ContainerStats>, Vec<String>)> {
+    let inspect_data = runtime.inspect(containers)?;
+
+    // containers can disappear between being listed and being inspected
+    let mut warnings = missing_containers(containers, &inspect_data)
+        .into_iter()
+        .map(|missing| format!("Container {missing} not found, skipping"))
+        .collect::<Vec<_>>();
+
+    // stopped containers have no stats to sample
+    let up_names = inspect_data
+        .iter()
+        .filter(|i| i.is_up())
+        .map(|i| i.container_name.clone())
+        .collect::<Vec<String>>();
+
+    let stats_data = if up_names.is_empty() {
+        vec![]
+    } else {
+        runtime.stats(&up_names)?
+    };
+
+    let stats_map = stats_data
+        .into_iter()
+        .map(|s| (s.container_name.clone(), s))
+        .collect::<HashMap<String, StatsData>>();
+
+    let mut rows = vec![];
+    for inspect in inspect_data {
+        let stats = stats_map.get(&inspect.container_name);
+        if stats.is_none() && inspect.is_up() {
+            warnings.push(format!("No stats reported for {}", inspect.container_name));
+        }
+
+        rows.push(ContainerStats {
+            name: inspect.container_name,
+            stack: inspect.stack,
+            status: inspect.status,
+            health: inspect.health,
+            restart_policy: inspect.restart_policy,
+            uptime: inspect.uptime,
+            started_at: inspect.started_at,
+            finished_at: inspect.finished_at,
+            restart_count: inspect.restart_count,
+            exit_code: inspect.exit_code,
+            oom_killed: inspect.oom_killed,
+            cpu_usage: stats.and_then(|s| s.cpu),
+            memory_usage: stats.and_then(|s| s.memory),
+            memory_bytes: stats.and_then(|s| s.memory_bytes),
+            memory_limit: stats.and_then(|s| s.memory_limit),
+            net_rx: stats.and_then(|s| s.net_io).map(|(rx, _)| rx),
+            net_tx: stats.and_then(|s| s.net_io).map(|(_, tx)| tx),
+            block_read: stats.and_then(|s| s.block_io).map(|(read, _)| read),
+            block_write: stats.and_then(|s| s.block_io).map(|(_, write)| write),
+            pids: stats.and_then(|s| s.pids),
+            image: inspect.image,
+            ports: inspect.ports,
+        });
+    }
+
+    Ok((rows, warnings))
+}
+
+/// Rank of a status, lower for states that need attention
+fn status_rank(status: &str) -> u8 {
+    match status.to_lowercase().as_str() {
+        "restarting" => 0,
+        "exited" | "dead" => 1,
+        "paused" => 2,
+        "created" => 3,
+        "running" => 5,
+        _ => 4,
+    }
+}
+
+/// Rank of a health state, lower for states that need attention
+fn health_rank(health: &str) -> u8 {
+    match health.to_lowercase().as_str() {
+        "unhealthy" => 0,
+        "starting" => 1,
+        "healthy" => 2,
+        _ => 3,
+    }
+}
+
+/// Orders unknown values after every known one, larger values first
+fn descending(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Sorts rows by `sort`, or by stack then name, containers outside a stack last
+pub fn sort_rows(rows: &mut [ContainerStats], sort: Option<StatsSort>, reverse: bool) {
+    rows.sort_by(|a, b| {
+        let by_column = match sort {
+            None | Some(StatsSort::Name) => Ordering::Equal,
+            Some(StatsSort::Cpu) => descending(a.cpu_usage, b.cpu_usage),
+            Some(StatsSort::Mem) => descending(a.memory_usage, b.memory_usage),
+            Some(StatsSort::Uptime) => descending(a.uptime_secs(), b.uptime_secs()),
+            Some(StatsSort::Status) => status_rank(&a.status).cmp(&status_rank(&b.status)),
+            Some(StatsSort::Health) => health_rank(&a.health).cmp(&health_rank(&b.health)),
+        };
+        let by_stack = match (&a.stack, &b.stack) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        };
+
+        if sort == Some(StatsSort::Name) {
+            a.name.cmp(&b.name).then(by_stack)
+        } else {
+            by_column.then(by_stack).then_with(|| a.name.cmp(&b.name))
+        }
+    });
+
+    if reverse {
+        rows.reverse();
+    }
+}
+
+/// Formats a percentage for display, or `--` when it is unknown
+fn format_percent(value: Option<f64>) -> String {
+    value.map_or_else(|| "--".to_string(), |v| format!("{v:.2}%"))
+}
+
+fn status_color(status: &str) -> Color {
+    match status.to_lowercase().as_str() {
+        "running" => Color::Green,
+        "created" => Color::Cyan,
+        "paused" | "restarting" => Color::Yellow,
+        _ => Color::Red,
+    }
+}
+
+fn health_color(health: &str) -> Color {
+    match health.to_lowercase().as_str() {
+        "healthy" => Color::Green,
+        "unhealthy" => Color::Red,
+        "starting" => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+impl ContainerStats {
+    /// Seconds since the container was started, None when it is not up
+    fn uptime_secs(&self) -> Option<f64> {
+        let up = matches!(self.status.as_str(), "running" | "paused" | "restarting");
+        self.started_at
+            .filter(|_| up)
+            .map(|started| (Utc::now() - started).num_milliseconds() as f64 / 1000.0)
+    }
+
+    /// Whether the container needs attention: unhealthy, restarting, OOM-killed or
+    /// restarted more than `max_restarts` times
+    pub fn is_problem(&self, max_restarts: u64) -> bool {
+        self.health.eq_ignore_ascii_case("unhealthy")
+            || self.status.eq_ignore_ascii_case("restarting")
+            || self.oom_killed
+            || self.restart_count > max_restarts
+    }
+
+    /// Value of a column with the color it is shown in
+    fn cell(&self, column: StatsColumn) -> (String, Option<Color>) {
+        let size = |bytes: Option<u64>| bytes.map_or_else(|| "--".to_string(), format_size);
+        let pair = |a: Option<u64>, b: Option<u64>| match a.zip(b) {
+            Some((a, b)) => format!("{} / {}", format_size(a), format_size(b)),
+            None => "--".to_string(),
+        };
+
+        match column {
+            StatsColumn::Name => (self.name.clone(), Some(Color::Cyan)),
+            StatsColumn::Stack => (self.stack.clone().unwrap_or_else(|| "-".to_string()), None),
+            StatsColumn::Status => (self.status.clone(), Some(status_color(&self.status))),
+            StatsColumn::Restart => (self.restart_policy.clone(), None),
+            StatsColumn::Restarts => {
+                let color = (self.restart_count > 0).then_some(Color::Yellow);
+                (self.restart_count.to_string(), color)
+            }
+            StatsColumn::Exitcode => {
+                let color = (self.exit_code != 0).then_some(Color::Red);
+                (self.exit_code.to_string(), color)
+            }
+            StatsColumn::Oom => match self.oom_killed {
+                true => ("yes".to_string(), Some(Color::Red)),
+                false => ("no".to_string(), None),
+            },
+            StatsColumn::Finished => (
+                self.finished_at.map_or_else(
+                    || "--".to_string(),
+                    |finished| format!("{} ago", format_elapsed(&finished)),
+                ),
+                None,
+            ),
+            StatsColumn::Health => (self.health.clone(), Some(health_color(&self.health))),
+            StatsColumn::Uptime => (self.uptime.clone(), None),
+            StatsColumn::Cpu => (format_percent(self.cpu_usage), None),
+            StatsColumn::Memperc => (format_percent(self.memory_usage), None),
+            StatsColumn::Mem => (size(self.memory_bytes), None),
+            StatsColumn::Memlimit => (size(self.memory_limit), None),
+            StatsColumn::Netio => (pair(self.net_rx, self.net_tx), None),
+            StatsColumn::Blockio => (pair(self.block_read, self.block_write), None),
+            StatsColumn::Pids => (
+                self.pids
+                    .map_or_else(|| "--".to_string(), |p| p.to_string()),
+                None,
+            ),
+            StatsColumn::Image => (self.image.clone(), None),
+            StatsColumn::Ports => (self.ports.clone(), None),
+        }
+    }
+}
+
+/// Colors or highlights a cell's text
+fn style(text: &str, color: Option<Color>, changed: bool) -> String {
+    match color {
+        _ if changed => highlight_fmt(text),
+        Some(color) => color_println_fmt(color, text),
+        None => text.to_string(),
+    }
+}
+
+/// Column headers, white above the columns that are colored
+fn render_header(columns: &[StatsColumn], use_color: bool) -> Vec<String> {
+    columns
+        .iter()
+        .map(|&column| {
+            let colored = matches!(
+                column,
+                StatsColumn::Name | StatsColumn::Status | StatsColumn::Health
+            );
+            let color = (use_color && colored).then_some(Color::White);
+            style(column.header(), color, false)
+        })
+        .collect()
+}
+
+/// Cells of a row, highlighting values that differ from the row's `previous` values
+fn render_row(
+    row: &ContainerStats,
+    previous: Option<&[ContainerStats]>,
+    columns: &[StatsColumn],
+    use_color: bool,
+) -> Vec<String> {
+    let before = previous.and_then(|p| p.iter().find(|r| r.name == row.name));
+
+    columns
+        .iter()
+        .map(|&column| {
+            let (text, color) = row.cell(column);
+            let changed = use_color && before.is_some_and(|b| b.cell(column).0 != text);
+            style(&text, color.filter(|_| use_color), changed)
+        })
+        .collect()
+}
+
+/// Totals of a group of rows, None for values no row in the group reported
+#[derive(Debug, Default, PartialEq)]
+struct GroupTotals {
+    containers: usize,
+    cpu_usage: Option<f64>,
+    memory_bytes: Option<u64>,
+    net_rx: Option<u64>,
+    net_tx: Option<u64>,
+    block_read: Option<u64>,
+    block_write: Option<u64>,
+    healthy: usize,
+    unhealthy: usize,
+}
+
+/// Sums the values that were reported, None when none were
+fn sum_reported<T: std::ops::Add<Output = T>>(
+    values: impl Iterator<Item = Option<T>>,
+) -> Option<T> {
+    values.flatten().reduce(|total, value| total + value)
+}
+
+fn group_totals(rows: &[&ContainerStats]) -> GroupTotals {
+    GroupTotals {
+        containers: rows.len(),
+        cpu_usage: sum_reported(rows.iter().map(|r| r.cpu_usage)),
+        memory_bytes: sum_reported(rows.iter().map(|r| r.memory_bytes)),
+        net_rx: sum_reported(rows.iter().map(|r| r.net_rx)),
+        net_tx: sum_reported(rows.iter().map(|r| r.net_tx)),
+        block_read: sum_reported(rows.iter().map(|r| r.block_read)),
+        block_write: sum_reported(rows.iter().map(|r| r.block_write)),
+        healthy: rows.iter().filter(|r| health_rank(&r.health) == 2).count(),
+        unhealthy: rows.iter().filter(|r| health_rank(&r.health) == 0).count(),
+    }
+}
+
+/// Renders the totals of a group of rows as
+/// `N container(s)  CPU 12.34%  MEM 1.5G  NET I/O 1K / 2K  BLOCK I/O --  2 healthy  1 unhealthy`
+fn render_totals(rows: &[&ContainerStats], use_color: bool) -> String {
+    let totals = group_totals(rows);
+    let size = |bytes: Option<u64>| bytes.map_or_else(|| "--".to_string(), format_size);
+    let pair = |a: Option<u64>, b: Option<u64>| match (a, b) {
+        (None, None) => "--".to_string(),
+        (a, b) => format!("{} / {}", size(a), size(b)),
+    };
+
+    let healthy = format!("{} healthy", totals.healthy);
+    let unhealthy = format!("{} unhealthy", totals.unhealthy);
+    let (healthy, unhealthy) = if use_color {
+        (
+            color_println_fmt(Color::Green, &healthy),
+            color_println_fmt(Color::Red, &unhealthy),
+        )
+    } else {
+        (healthy, unhealthy)
+    };
+
+    format!(
+        "{} container(s)  CPU {}  MEM {}  NET I/O {}  BLOCK I/O {}  {healthy}  {unhealthy}",
+        totals.containers,
+        format_percent(totals.cpu_usage),
+        size(totals.memory_bytes),
+        pair(totals.net_rx, totals.net_tx),
+        pair(totals.block_read, totals.block_write),
+    )
+}
+
+/// Renders the stats table, highlighting values that differ from `previous` when colored
+///
+/// Grouped rows keep their order within a group, groups are ordered by their first row.
+pub fn render_table(
+    rows: &[ContainerStats],
+    previous: Option<&[ContainerStats]>,
+    args: &StatsArgs,
+    use_color: bool,
+) -> String {
+    let columns = &args.columns;
+    let mut table = Table::new(render_header(columns, use_color))
+        .border(args.border)
+        .max_width(terminal_width());
+    // spacing lines only make sense without borders
+    let spacer = |table: &mut Table| {
+        if !args.border {
+            table.text(String::new());
+        }
+    };
+    spacer(&mut table);
+
+    let Some(GroupBy::Stack) = args.group_by else {
+        for row in rows {
+            table.row(render_row(row, previous, columns, use_color));
+        }
+        return table.render();
+    };
+
+    let mut groups: Vec<(Option<&str>, Vec<&ContainerStats>)> = vec![];
+    for row in rows {
+        let stack = row.stack.as_deref();
+        match groups.iter_mut().find(|(s, _)| *s == stack) {
+            Some((_, group)) => group.push(row),
+            None => groups.push((stack, vec![row])),
+        }
+    }
+
+    for (i, (stack, group)) in groups.iter().enumerate() {
+        if i > 0 {
+            spacer(&mut table);
+        }
+
+        let title = stack.unwrap_or("(no stack)");
+        if use_color {
+            table.text(color_println_fmt(Color::Magenta, title));
+        } else {
+            table.text(title.to_string());
+        }
+
+        for row in group {
+            table.row(render_row(row, previous, columns, use_color));
+        }
+        table.text(format!("  {}", render_totals(group, use_color)));
+    }
+
+    table.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, stack: Option<&str>) -> ContainerStats {
+        ContainerStats {
+            name: name.to_string(),
+            stack: stack.map(str::to_string),
+            status: "running".to_string(),
+            health: String::new(),
+            restart_policy: "no".to_string(),
+            uptime: String::new(),
+            started_at: None,
+            finished_at: None,
+            restart_count: 0,
+            exit_code: 0,
+            oom_killed: false,
+            cpu_usage: None,
+            memory_usage: None,
+            memory_bytes: None,
+            memory_limit: None,
+            net_rx: None,
+            net_tx: None,
+            block_read: None,
+            block_write: None,
+            pids: None,
+            image: String::new(),
+            ports: String::new(),
+        }
+    }
+
+    fn sorted(
+        mut rows: Vec<ContainerStats>,
+        sort: Option<StatsSort>,
+        reverse: bool,
+    ) -> Vec<String> {
+        sort_rows(&mut rows, sort, reverse);
+        rows.into_iter().map(|r| r.name).collect()
+    }
+
+    /// Rows of two stacks and one without a stack, `values` per row in the same order
+    fn rows_with<T>(values: [T; 4], set: impl Fn(&mut ContainerStats, T)) -> Vec<ContainerStats> {
+        let mut rows = vec![
+            row("loose", None),
+            row("web", Some("media")),
+            row("db", Some("media")),
+            row("proxy", Some("edge")),
+        ];
+        for (row, value) in rows.iter_mut().zip(values) {
+            set(row, value);
+        }
+        rows
+    }
+
+    #[test]
+    fn default_order_is_stack_then_name_without_stack_last() {
+        let rows = rows_with([(); 4], |_, _| {});
+
+        assert_eq!(
+            sorted(rows.clone(), None, false),
+            ["proxy", "db", "web", "loose"]
+        );
+        assert_eq!(sorted(rows, None, true), ["loose", "web", "db", "proxy"]);
+    }
+
+    #[test]
+    fn sort_by_name_ignores_stacks() {
+        let mut rows = rows_with([(); 4], |_, _| {});
+        rows.push(row("db", None));
+
+        assert_eq!(
+            sorted(rows, Some(StatsSort::Name), false),
+            ["db", "db", "loose", "proxy", "web"]
+        );
+    }
+
+    #[test]
+    fn sort_by_cpu_and_mem_puts_the_largest_first_and_unknown_last() {
+        let cpu = rows_with([Some(5.0), None, Some(12.5), Some(5.0)], |r, v| {
+            r.cpu_usage = v
+        });
+        let mem = rows_with([Some(1.0), Some(80.0), None, Some(9.5)], |r, v| {
+            r.memory_usage = v
+        });
+
+        // ties are broken by stack and name
+        assert_eq!(
+            sorted(cpu.clone(), Some(StatsSort::Cpu), false),
+            ["db", "proxy", "loose", "web"]
+        );
+        assert_eq!(
+            sorted(cpu, Some(StatsSort::Cpu), true),
+            ["web", "loose", "proxy", "db"]
+        );
+        // numeric rather than string order: 80 > 9.5
+        assert_eq!(
+            sorted(mem, Some(StatsSort::Mem), false),
+            ["web", "proxy", "loose", "db"]
+        );
+    }
+
+    #[test]
+    fn sort_by_uptime_puts_the_longest_running_first() {
+        let now = Utc::now();
+        let rows = rows_with(
+            [
+                (now - chrono::Duration::hours(1), "running"),
+                (now - chrono::Duration::days(2), "exited"),
+                (now - chrono::Duration::days(1), "running"),
+                (now - chrono::Duration::hours(1), "paused"),
+            ],
+            |r, (started, status)| {
+                r.started_at = Some(started);
+                r.status = status.to_string();
+            },
+        );
+
+        // stopped containers have no uptime, however long ago they started
+        assert_eq!(
+            sorted(rows, Some(StatsSort::Uptime), false),
+            ["db", "proxy", "loose", "web"]
+        );
+    }
+
+    #[test]
+    fn sort_by_status_and_health_puts_problems_first() {
+        let status = rows_with(["running", "exited", "Restarting", "running"], |r, v| {
+            r.status = v.to_string()
+        });
+        let health = rows_with(["healthy", "", "unhealthy", "starting"], |r, v| {
+            r.health = v.to_string()
+        });
+
+        assert_eq!(
+            sorted(status, Some(StatsSort::Status), false),
+            ["db", "web", "proxy", "loose"]
+        );
+        assert_eq!(
+            sorted(health.clone(), Some(StatsSort::Health), false),
+            ["db", "proxy", "loose", "web"]
+        );
+        assert_eq!(
+            sorted(health, Some(StatsSort::Health), true),
+            ["web", "loose", "proxy", "db"]
+        );
+    }
+
+    #[test]
+    fn group_totals_sum_the_reported_values() {
+        let mut web = row("web", Some("media"));
+        web.cpu_usage = Some(1.5);
+        web.memory_bytes = Some(1024);
+        web.net_rx = Some(100);
+        web.net_tx = Some(50);
+        web.health = "healthy".to_string();
+        let mut db = row("db", Some("media"));
+        db.cpu_usage = Some(2.25);
+        db.memory_bytes = Some(2048);
+        db.net_rx = Some(1);
+        db.health = "unhealthy".to_string();
+        // stopped: no stats at all
+        let mut old = row("old", Some("media"));
+        old.status = "exited".to_string();
+        old.health = "healthy".to_string();
+
+        assert_eq!(
+            group_totals(&[&web, &db, &old]),
+            GroupTotals {
+                containers: 3,
+                cpu_usage: Some(3.75),
+                memory_bytes: Some(3072),
+                net_rx: Some(101),
+                net_tx: Some(50),
+                block_read: None,
+                block_write: None,
+                healthy: 2,
+                unhealthy: 1,
+            }
+        );
+        assert_eq!(
+            group_totals(&[&old]),
+            GroupTotals {
+                containers: 1,
+                healthy: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn render_totals_shows_unknown_sums_as_dashes() {
+        let mut web = row("web", None);
+        web.cpu_usage = Some(12.5);
+        web.memory_bytes = Some(1536);
+        web.block_read = Some(2048);
+
+        assert_eq!(
+            render_totals(&[&web, &row("old", None)], false),
+            format!(
+                "2 container(s)  CPU 12.50%  MEM {}  NET I/O --  BLOCK I/O {} / --  0 healthy  0 unhealthy",
+                format_size(1536),
+                format_size(2048)
+            )
+        );
+    }
+
+    #[test]
+    fn is_problem_flags_containers_that_need_attention() {
+        let cases: [(&str, &str, &str, bool, u64, bool); 9] = [
+            // (case, status, health, oom_killed, restart_count, problem)
+            ("healthy", "running", "healthy", false, 0, false),
+            ("no healthcheck", "running", "", false, 0, false),
+            ("starting", "running", "starting", false, 0, false),
+            ("unhealthy", "running", "unhealthy", false, 0, true),
+            (
+                "unhealthy mixed case",
+                "running",
+                "Unhealthy",
+                false,
+                0,
+                true,
+            ),
+            ("restarting", "restarting", "", false, 0, true),
+            ("oom killed", "exited", "", true, 0, true),
+            ("restarts at the limit", "running", "", false, 3, false),
+            ("restarts over the limit", "running", "", false, 4, true),
+        ];
+
+        for (case, status, health, oom_killed, restart_count, problem) in cases {
+            let mut row = row("web", None);
+            row.status = status.to_string();
+            row.health = health.to_string();
+            row.oom_killed = oom_killed;
+            row.restart_count = restart_count;
+
+            assert_eq!(row.is_problem(3), problem, "{case}");
+        }
+    }
+}
@@ -1,345 +1,524 @@
-use crate::commands::DOCKER;
-use crate::printer::{color_println, color_println_fmt, Color};
+use crate::models::{CliStats, ContainerInspect};
+use crate::printer::{color_println, Color};
+use crate::runtime::ContainerRuntime;
 use anyhow::Context;
 use chrono::{DateTime, Local, Utc};
-use std::io::{BufRead, BufReader, IsTerminal};
-use std::process::{Command, Stdio};
-use std::sync::Arc;
+use serde::Serialize;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
 
 /// Determine if stdout is going to terminal
 pub fn is_terminal() -> bool {
     std::io::stdout().is_terminal()
 }
 
+/// Determine if an executable with the given name exists on the PATH
+pub fn find_in_path(binary: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(binary).is_file()))
+}
+
 /// Gets the current time on the system in readable format
 pub fn get_timestamp() -> String {
     Local::now().format("%Y-%m-%dT%H:%M:%S").to_string()
 }
 
-/// Lists currently running docker containers
-pub fn list_containers() -> anyhow::Result<Vec<String>> {
-    if is_terminal() {
-        color_println(Color::Magenta, "Listing docker containers...");
+/// Parses a duration like `500ms`, `90s`, `30m`, `2h` or `1h30m` (units ms, s, m, h, d, w)
+pub fn parse_duration(value: &str) -> anyhow::Result<chrono::Duration> {
+    let value = value.trim();
+    let invalid =
+        || anyhow::anyhow!("Invalid duration: {value}, expected e.g. 30s, 10m, 2h or 1h30m");
+
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = chrono::Duration::zero();
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        rest = &rest[unit_len..];
+
+        let part = match unit {
+            "ms" => chrono::Duration::try_milliseconds(amount),
+            "s" => chrono::Duration::try_seconds(amount),
+            "m" => chrono::Duration::try_minutes(amount),
+            "h" => chrono::Duration::try_hours(amount),
+            "d" => chrono::Duration::try_days(amount),
+            "w" => chrono::Duration::try_weeks(amount),
+            _ => {
+                anyhow::bail!("Invalid duration unit '{unit}' in {value}, use ms, s, m, h, d or w")
+            }
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(invalid)?;
     }
 
-    // Use docker to list container_ids
-    let container_ids = Command::new(DOCKER)
-        .args(["ps", "-q"])
-        .output()
-        .context("Failed to list docker containers")?;
+    Ok(total)
+}
 
-    // Turn Output into String
-    let container_id_list = String::from_utf8(container_ids.stdout)
-        .context("Failed to create string of container id's")?;
+/// Parses a duration argument into a std Duration, for use as a clap value parser
+pub fn parse_std_duration(value: &str) -> anyhow::Result<std::time::Duration> {
+    parse_duration(value)?
+        .to_std()
+        .with_context(|| format!("Duration must not be negative: {value}"))
+}
 
-    // Parse/sanitize container ids and collecto into Vec
-    let ids = container_id_list
-        .split_whitespace()
-        .map(String::from)
-        .collect::<Vec<String>>();
+/// Parses a size like `512K`, `10M` or `1G` into bytes (binary units, a trailing `B` or
+/// `iB` is accepted), for use as a clap value parser
+pub fn parse_size(value: &str) -> anyhow::Result<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let amount: u64 = value[..digits]
+        .parse()
+        .with_context(|| format!("Invalid size: {value}, expected e.g. 512K, 10M or 1G"))?;
+
+    let unit = value[digits..].trim().to_ascii_uppercase();
+    let multiplier: u64 = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => anyhow::bail!("Invalid size unit '{unit}' in {value}, use K, M or G"),
+    };
 
-    Ok(ids)
+    amount
+        .checked_mul(multiplier)
+        .with_context(|| format!("Size too large: {value}"))
 }
 
-/// Force removes all docker containers provided in argument
-pub fn kill_containers(container_ids: Vec<String>) -> anyhow::Result<()> {
-    if is_terminal() {
-        color_println(Color::Yellow, "Killing docker containers...");
-    } else {
-        println!("Killing docker containers...")
+/// Formats a byte count with binary units, e.g. `1.5M`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+
+    if bytes < 1024 {
+        return format!("{bytes}B");
     }
 
-    Command::new(DOCKER)
-        .args(["rm", "-f"])
-        .args(&container_ids)
-        .status()
-        .context("Failed to remove containers")?;
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
 
-    Ok(())
+    format!("{size:.1}{}", UNITS[unit])
 }
 
-/// Gets container names from a given stack
-pub fn get_containers_from_stack(stack: &str) -> anyhow::Result<Vec<String>> {
-    let output = Command::new(DOCKER)
-        .args([
-            "ps",
-            "-q",
-            "--filter",
-            &format!("label=com.docker.compose.project={}", &stack),
-        ])
-        .output()
-        .context(format!("Failed to containers in stack: {}", &stack))?;
+/// Size of a container's log file together with the files it was rotated to
+/// (`<LogPath>.1`, `<LogPath>.2.gz`, ...), None when it can't be read
+pub fn log_file_size(log_path: &str) -> Option<u64> {
+    let path = Path::new(log_path);
+    let mut total = fs::metadata(path).ok()?.len();
+
+    let name = path.file_name()?.to_string_lossy();
+    let rotated_prefix = format!("{name}.");
+    if let Some(Ok(entries)) = path.parent().map(fs::read_dir) {
+        total += entries
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&rotated_prefix))
+            .filter_map(|e| e.metadata().ok())
+            .map(|m| m.len())
+            .sum::<u64>();
+    }
 
-    let container_ids =
-        String::from_utf8(output.stdout).expect("Failed to parse container name from output");
+    Some(total)
+}
+
+/// Parses a point in time given as a relative duration before now (e.g. `2h`),
+/// an RFC3339 timestamp or unix seconds
+pub fn parse_time_arg(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Some(time) = value
+        .parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+    {
+        return Ok(time);
+    }
 
-    let container_ids_vec = container_ids.split_whitespace().map(String::from);
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
 
-    let containers = container_ids_vec
-        .filter_map(|id| get_container_name(&id).ok())
-        .collect();
+    let duration = parse_duration(value).with_context(|| {
+        format!("Invalid time: {value}, expected e.g. 2h, 1h30m or 2024-01-01T00:00:00Z")
+    })?;
 
-    Ok(containers)
+    Utc::now()
+        .checked_sub_signed(duration)
+        .with_context(|| format!("Invalid duration: {value}, too far in the past"))
 }
 
-/// Gets the name of a docker container by the container_id passed as argument
-pub fn get_container_name(container_id: &str) -> anyhow::Result<String> {
-    // get container name by referencing id
-    let output = Command::new(DOCKER)
-        .args(["inspect", "--format", "{{.Name}}", container_id])
-        .output()
-        .context("Failed to inspect container")?;
-
-    // parse output into clean String
-    let name = String::from_utf8(output.stdout)
-        .context("Failed to parse container name from output")?
-        .trim()
-        .trim_start_matches('/') // Docker names start with '/'
-        .to_string();
-
-    Ok(name)
+/// Formats a time as fractional unix seconds, as accepted by `docker logs --since/--until`
+pub fn unix_timestamp(time: &DateTime<Utc>) -> String {
+    format!("{}.{:09}", time.timestamp(), time.timestamp_subsec_nanos())
 }
 
-/// Updates a container by the container_name provided as argument
-pub fn update_container_by_name(container_name: &str) -> anyhow::Result<u8> {
-    let mut is_updated: u8 = 0;
-    // get container image string by referenciing the container_name
-    let image_output = Command::new(DOCKER)
-        .args(["inspect", "--format", "{{.Config.Image}}", container_name])
-        .output()
-        .context("Failed to inspect container")?;
-
-    // parse output into clean String
-    let image_name = String::from_utf8(image_output.stdout)
-        .context("Failed to parse image name from output")?
-        .trim()
-        .to_string();
-
+/// Lists currently running docker containers
+pub fn list_containers(runtime: &dyn ContainerRuntime) -> anyhow::Result<Vec<String>> {
     if is_terminal() {
-        color_println(
-            Color::Cyan,
-            &format!("Pulling image for {}: {}", &container_name, &image_name),
-        );
-    } else {
-        println!("Pulling image for {}: {}", &container_name, &image_name)
+        color_println(Color::Magenta, "Listing docker containers...");
     }
 
-    // pull new image for container
-    let mut logs_process = Command::new(DOCKER)
-        .args(["pull", &image_name])
-        .stdout(Stdio::piped())
-        .spawn()
-        .context(format!("Failed to pull image: {}", &image_name))?;
-
-    if let Some(stdout) = logs_process.stdout.take() {
-        let reader = BufReader::new(stdout);
-        for line in reader.lines().map_while(Result::ok) {
-            println!("{line}");
-            if line.contains("Status: Downloaded newer image") {
-                is_updated = 1
-            }
-        }
+    let containers = runtime.list(false)?;
+
+    Ok(containers.into_iter().map(|c| c.id).collect())
+}
+
+/// Force removes all docker containers provided in argument
+pub fn kill_containers(
+    runtime: &dyn ContainerRuntime,
+    container_ids: Vec<String>,
+) -> anyhow::Result<()> {
+    if is_terminal() {
+        color_println(Color::Yellow, "Killing docker containers...");
+    } else {
+        println!("Killing docker containers...")
     }
 
-    let _ = logs_process.kill();
-    let _ = logs_process.wait();
+    runtime.remove(&container_ids)
+}
 
-    Ok(is_updated)
+/// Progress reported while updating a container
+#[derive(Debug, Clone, Copy)]
+pub enum UpdateProgress<'a> {
+    /// The container's image is about to be pulled
+    Pulling { container: &'a str, image: &'a str },
+    /// A progress message from the runtime
+    Message(&'a str),
 }
 
-/// Spawns threads to handle container logs
-pub fn spawn_container_logger(
-    container: &str,
-    is_container_id: bool,
-    use_color: bool,
-    tail: u32,
-    tx: std::sync::mpsc::Sender<String>,
-) -> anyhow::Result<std::thread::JoinHandle<()>> {
-    let container_identifier = Arc::new(container.to_string());
-
-    let handle = std::thread::spawn(move || {
-        let container_name = if is_container_id {
-            match get_container_name(&container_identifier) {
-                Ok(name) => Arc::new(name),
-                Err(_) => Arc::clone(&container_identifier),
-            }
-        } else {
-            Arc::clone(&container_identifier)
-        };
+/// Outcome of pulling the image of a container
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpdateResult {
+    pub container: String,
+    pub image: String,
+    /// Whether a newer image was pulled
+    pub updated: bool,
+    /// Digest of the pulled image, when the registry reported one
+    pub digest: Option<String>,
+    /// Why the image could not be pulled
+    pub error: Option<String>,
+}
 
-        let mut logs_process = match Command::new(DOCKER)
-            .args([
-                "logs",
-                &container_name,
-                "--tail",
-                &tail.to_string(),
-                "--follow",
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-        {
-            Ok(proc) => proc,
-            Err(_) => {
-                let _ = tx.send(if use_color {
-                    color_println_fmt(
-                        Color::Red,
-                        &format!("[ERROR] - Failed to log {container_name}"),
-                    )
-                } else {
-                    format!("[ERROR] - Failed to log {container_name}")
-                });
-                return;
-            }
-        };
+/// Updates a container by the container_name provided as argument
+///
+/// Failures are recorded in the result, so the remaining containers can still be updated.
+pub fn update_container_by_name(
+    runtime: &dyn ContainerRuntime,
+    container_name: &str,
+    progress: &mut dyn FnMut(UpdateProgress),
+) -> UpdateResult {
+    let mut result = UpdateResult {
+        container: container_name.to_string(),
+        ..Default::default()
+    };
 
-        let mut handles: Vec<std::thread::JoinHandle<()>> = vec![];
-
-        // handle stdout
-        if let Some(stdout) = logs_process.stdout.take() {
-            let tx_stdout = tx.clone();
-            let container_name_stdout = Arc::clone(&container_name);
-            let handle_stdout = std::thread::spawn(move || {
-                let reader = BufReader::new(stdout);
-                for line in reader.lines().map_while(Result::ok) {
-                    if tx_stdout
-                        .send(if use_color {
-                            format!(
-                                "[{} | {}] {}",
-                                color_println_fmt(Color::Cyan, &get_timestamp()),
-                                color_println_fmt(Color::Green, &container_name_stdout),
-                                line
-                            )
-                        } else {
-                            format!(
-                                "[{} | {}] {}",
-                                &get_timestamp(),
-                                &container_name_stdout,
-                                line
-                            )
-                        })
-                        .is_err()
-                    {
-                        break; // Receiver closed
-                    }
-                }
-            });
-
-            handles.push(handle_stdout);
+    // get container image string by referencing the container_name
+    let image_name = runtime
+        .inspect(&[container_name.to_string()])
+        .and_then(|inspected| {
+            inspected
+                .into_iter()
+                .next()
+                .with_context(|| format!("Failed to inspect container {container_name}"))
+        })
+        .map(|inspect| inspect.image);
+    result.image = match image_name {
+        Ok(image) => image,
+        Err(e) => {
+            result.error = Some(format!("{e:#}"));
+            return result;
         }
+    };
 
-        // handle stderr
-        if let Some(stderr) = logs_process.stderr.take() {
-            let tx_stderr = tx.clone();
-            let container_name_stderr = Arc::clone(&container_name);
-            let handle_stderr = std::thread::spawn(move || {
-                let reader = BufReader::new(stderr);
-                for line in reader.lines().map_while(Result::ok) {
-                    if tx_stderr
-                        .send(if use_color {
-                            format!(
-                                "[{} | {}] {}",
-                                color_println_fmt(Color::Cyan, &get_timestamp()),
-                                color_println_fmt(Color::Green, &container_name_stderr),
-                                line
-                            )
-                        } else {
-                            format!(
-                                "[{} | {}] {}",
-                                &get_timestamp(),
-                                &container_name_stderr,
-                                line
-                            )
-                        })
-                        .is_err()
-                    {
-                        break; // Receiver closed
-                    }
-                }
-            });
-
-            handles.push(handle_stderr);
-        }
+    progress(UpdateProgress::Pulling {
+        container: container_name,
+        image: &result.image,
+    });
 
-        for handle in handles {
-            let _ = handle.join();
+    // pull new image for container
+    match runtime.pull(&result.image, &mut |line| {
+        progress(UpdateProgress::Message(line))
+    }) {
+        Ok(pulled) => {
+            result.updated = pulled.updated;
+            result.digest = pulled.digest;
         }
+        Err(e) => result.error = Some(format!("{e:#}")),
+    }
 
-        let _ = logs_process.kill();
-        let _ = logs_process.wait();
-    });
+    result
+}
 
-    Ok(handle)
+/// Outcome of restarting a container
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartResult {
+    pub container: String,
+    pub restarted: bool,
+    /// Why the container could not be restarted
+    pub error: Option<String>,
+}
+
+/// Restarts a container, recording a failure in the result
+pub fn restart_container(runtime: &dyn ContainerRuntime, container: &str) -> RestartResult {
+    let restarted = runtime
+        .restart(container)
+        .with_context(|| format!("Failed to restart {container}"));
+
+    RestartResult {
+        container: container.to_string(),
+        restarted: restarted.is_ok(),
+        error: restarted.err().map(|e| format!("{e:#}")),
+    }
 }
 
 /// Shape of stats data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct StatsData {
     pub container_name: String,
-    pub cpu: String,
-    pub memory: String,
+    /// CPU usage in percent, None when the runtime reported no value
+    pub cpu: Option<f64>,
+    /// Memory usage in percent of the limit, None when the runtime reported no value
+    pub memory: Option<f64>,
+    /// Memory used in bytes, not counting page cache
+    pub memory_bytes: Option<u64>,
+    /// Memory limit in bytes, the host's memory when the container has no limit
+    pub memory_limit: Option<u64>,
+    /// Bytes received and sent over all networks
+    pub net_io: Option<(u64, u64)>,
+    /// Bytes read from and written to block devices
+    pub block_io: Option<(u64, u64)>,
+    /// Number of processes and threads in the container
+    pub pids: Option<u64>,
 }
 
-/// Parse stats data
+/// Parses one line of `docker stats --format '{{json .}}'` output
 pub fn parse_stats_data(stats: &str) -> anyhow::Result<StatsData> {
-    let parsed = stats
-        .trim_start_matches("/")
-        .split_whitespace()
-        .collect::<Vec<&str>>();
-
-    Ok(StatsData {
-        container_name: parsed[0].to_string(),
-        cpu: parsed[1].to_string(),
-        memory: parsed[2].to_string(),
-    })
+    let parsed: CliStats = serde_json::from_str(stats)
+        .with_context(|| format!("Failed to parse stats line: {stats}"))?;
+
+    Ok(parsed.into())
 }
 
 /// Shape of inspected data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct InspectData {
+    pub id: String,
     pub container_name: String,
     pub status: String,
     pub restart_policy: String,
     pub health: String,
     pub uptime: String,
+    /// When the container was last started, None if it never was
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the container last exited, None if it never did
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Times the runtime restarted the container because of its restart policy
+    pub restart_count: u64,
+    /// Exit code of the last run
+    pub exit_code: i64,
+    /// Whether the last run was killed for running out of memory
+    pub oom_killed: bool,
+    pub image: String,
+    /// Compose project (stack) the container belongs to
+    pub stack: Option<String>,
     pub ports: String,
+    /// Path of the json-file log, empty when the runtime logs elsewhere
+    pub log_path: String,
+}
+
+impl InspectData {
+    /// Whether the container runs, possibly paused, so the runtime reports stats for it
+    pub fn is_up(&self) -> bool {
+        matches!(self.status.as_str(), "running" | "paused")
+    }
 }
 
-/// Parses inspected data
-pub fn parse_inspect_data(stats: &str) -> anyhow::Result<InspectData> {
-    let parsed = stats
-        .trim_start_matches("/")
-        .split(",")
-        .collect::<Vec<&str>>();
-
-    Ok(InspectData {
-        container_name: parsed[0].to_string(),
-        status: parsed[1].to_string(),
-        restart_policy: parsed[2].to_string(),
-        health: parsed[3].to_string(),
-        uptime: calc_uptime(parsed[4])?,
-        ports: parsed[5].to_string(),
-    })
+/// Parses the JSON array printed by `docker inspect`
+pub fn parse_inspect_data(inspect: &str) -> anyhow::Result<Vec<InspectData>> {
+    // docker prints an empty array when none of the containers exist
+    if inspect.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let parsed: Vec<ContainerInspect> =
+        serde_json::from_str(inspect).context("Failed to parse inspect output")?;
+
+    Ok(parsed.into_iter().map(InspectData::from).collect())
 }
 
-/// Calculate uptime for a container
-fn calc_uptime(start_time: &str) -> anyhow::Result<String> {
-    let start_time =
-        DateTime::parse_from_rfc3339(start_time).context("Failed to parse start_time")?;
-    let now = Utc::now();
-    let duration = now.signed_duration_since(start_time.with_timezone(&Utc));
+/// Finds requested containers (by name or id prefix) that are missing from inspect results
+pub fn missing_containers(requested: &[String], inspected: &[InspectData]) -> Vec<String> {
+    requested
+        .iter()
+        .filter(|r| {
+            !inspected
+                .iter()
+                .any(|i| &i.container_name == *r || (!r.is_empty() && i.id.starts_with(r.as_str())))
+        })
+        .cloned()
+        .collect()
+}
+/// Formats the time passed since `since` as `1D 2H 3m`, leaving out leading zero units
+pub fn format_elapsed(since: &DateTime<Utc>) -> String {
+    let duration = Utc::now().signed_duration_since(since);
 
     let days = duration.num_days();
     let hours = duration.num_hours() % 24;
     let minutes = duration.num_minutes() % 60;
 
-    let uptime = if days > 0 {
+    if days > 0 {
         format!("{days}D {hours}H {minutes}m")
     } else if hours > 0 {
         format!("{hours}H {minutes}m")
     } else {
         format!("{minutes}m")
-    };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn parse_duration_reads_each_unit_and_combinations() {
+        assert_eq!(
+            parse_duration("500ms").unwrap(),
+            Duration::milliseconds(500)
+        );
+        assert_eq!(parse_duration("90s").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_duration(" 2h ").unwrap(), Duration::hours(2));
+        assert_eq!(parse_duration("3d").unwrap(), Duration::days(3));
+        assert_eq!(parse_duration("1w").unwrap(), Duration::weeks(1));
+        assert_eq!(
+            parse_duration("1h30m").unwrap(),
+            Duration::hours(1) + Duration::minutes(30)
+        );
+        assert_eq!(parse_duration("0s").unwrap(), Duration::zero());
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for value in ["", "10", "m", "1h30", "-5m", "soon"] {
+            let error = parse_duration(value).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Invalid duration: {value}, expected e.g. 30s, 10m, 2h or 1h30m"),
+                "{value:?}"
+            );
+        }
+        assert_eq!(
+            parse_duration("5y").unwrap_err().to_string(),
+            "Invalid duration unit 'y' in 5y, use ms, s, m, h, d or w"
+        );
+        assert_eq!(
+            parse_duration("1.5h").unwrap_err().to_string(),
+            "Invalid duration unit '.' in 1.5h, use ms, s, m, h, d or w"
+        );
+    }
+
+    #[test]
+    fn parse_duration_rejects_out_of_range_values() {
+        for value in [
+            "99999999999999999999s",
+            "9999999999999999w",
+            "9223372036854775s1000s",
+        ] {
+            assert!(parse_duration(value).is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn parse_std_duration_converts_for_clap() {
+        assert_eq!(
+            parse_std_duration("2s").unwrap(),
+            std::time::Duration::from_secs(2)
+        );
+        assert!(parse_std_duration("2x").is_err());
+    }
+
+    #[test]
+    fn parse_time_arg_reads_rfc3339_and_unix_seconds() {
+        let expected = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(parse_time_arg("2024-01-01T00:00:00Z").unwrap(), expected);
+        assert_eq!(
+            parse_time_arg("2024-01-01T02:00:00+02:00").unwrap(),
+            expected
+        );
+        assert_eq!(parse_time_arg("1704067200").unwrap(), expected);
+    }
 
-    Ok(uptime)
+    #[test]
+    fn parse_time_arg_reads_durations_relative_to_now() {
+        let before = Utc::now();
+        let time = parse_time_arg("1h30m").unwrap();
+        let after = Utc::now();
+
+        let ninety_minutes = Duration::minutes(90);
+        assert!(before - ninety_minutes <= time && time <= after - ninety_minutes);
+    }
+
+    #[test]
+    fn parse_time_arg_rejects_garbage_and_times_out_of_range() {
+        assert_eq!(
+            parse_time_arg("yesterday").unwrap_err().to_string(),
+            "Invalid time: yesterday, expected e.g. 2h, 1h30m or 2024-01-01T00:00:00Z"
+        );
+        assert!(parse_time_arg("2024-13-01T00:00:00Z").is_err());
+        assert_eq!(
+            parse_time_arg("100000000w").unwrap_err().to_string(),
+            "Invalid duration: 100000000w, too far in the past"
+        );
+    }
+
+    #[test]
+    fn parse_size_reads_binary_units() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("10M").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1G").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1GiB").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_size("2kb").unwrap(), 2048);
+        assert_eq!(parse_size("64 MB").unwrap(), 64 * 1024 * 1024);
+        assert_eq!(parse_size("7B").unwrap(), 7);
+    }
+
+    #[test]
+    fn parse_size_rejects_garbage_and_overflow() {
+        assert_eq!(
+            parse_size("K").unwrap_err().to_string(),
+            "Invalid size: K, expected e.g. 512K, 10M or 1G"
+        );
+        assert!(parse_size("").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert_eq!(
+            parse_size("1T").unwrap_err().to_string(),
+            "Invalid size unit 'T' in 1T, use K, M or G"
+        );
+        assert_eq!(
+            parse_size("99999999999G").unwrap_err().to_string(),
+            "Size too large: 99999999999G"
+        );
+    }
 }